@@ -1,14 +1,17 @@
 use anyhow::anyhow;
 use fixity_store::{
-    container::{Container, DefaultContainer, PersistContainer},
+    car,
+    container::{Container, PersistContainer},
     content_store::ContentStore,
     contentid::Cid,
     meta_store::{MetaStore, MetaStoreError},
     replicaid::Rid,
+    replicakey::{ReplicaKey, ReplicaSig},
     stores::memory::Memory,
 };
 use fixity_structs::replicalog::ReplicaLog;
 use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
     ops::{Deref, DerefMut},
     sync::Arc,
 };
@@ -33,7 +36,12 @@ where
     pub fn new(meta: Arc<M>, store: Arc<S>) -> Self {
         Self { meta, store }
     }
-    pub async fn open<T>(&self, repo: &str, replica_id: Rid) -> Result<RepoReplica<M, S, T>, Error>
+    /// Open the given repo as the replica of the given key.
+    pub async fn open<T>(
+        &self,
+        repo: &str,
+        replica_key: &ReplicaKey,
+    ) -> Result<RepoReplica<M, S, T>, Error>
     where
         T: Container<S>,
     {
@@ -42,10 +50,62 @@ where
             Arc::clone(&self.meta),
             Arc::clone(&self.store),
             repo,
-            replica_id,
+            replica_key.clone(),
         )
         .await
     }
+    /// Open the given repo as the replica of the given key, merging in the repo of every other
+    /// replica of the same identity.
+    ///
+    /// See [`RepoReplica::merge_identity`].
+    pub async fn open_identity<T>(
+        &self,
+        repo: &str,
+        replica_key: &ReplicaKey,
+    ) -> Result<RepoReplica<M, S, T>, Error>
+    where
+        T: Container<S>,
+    {
+        let mut repo = self.open::<T>(repo, replica_key).await?;
+        repo.merge_identity().await?;
+        Ok(repo)
+    }
+    /// Claim the replica `rid` as part of the identity of the replica of the given key.
+    ///
+    /// The `ack` is the signature of `rid` acknowledging the claim, see
+    /// [`fixity_structs::replicalog::ack_claim`].
+    pub async fn claim_replica(
+        &self,
+        replica_key: &ReplicaKey,
+        rid: Rid,
+        ack: ReplicaSig,
+    ) -> Result<(), Error> {
+        let mut log = open_log(self.meta.as_ref(), &self.store, replica_key.clone()).await?;
+        log.claim_replica(&self.store, rid, ack)
+            .await
+            .map_err(|err| Error::Other(anyhow!(err)))?;
+        let log_tip = log
+            .save(&self.store)
+            .await
+            .map_err(|err| Error::Other(anyhow!(err)))?;
+        self.meta
+            .set_head("local", &replica_key.rid(), log_tip)
+            .await
+            .map_err(|err| Error::Other(anyhow!(err)))
+    }
+    /// Resolve all replicas of the identity that `rid` belongs to, including `rid` itself.
+    pub async fn identity_replicas(&self, rid: Rid) -> Result<Vec<Rid>, Error> {
+        identity_replicas(self.meta.as_ref(), &self.store, rid).await
+    }
+    /// Import the blocks of a CARv1 into the store, returning the roots of the CAR.
+    pub async fn import_car<R>(&self, r: R) -> Result<Vec<Cid>, Error>
+    where
+        R: std::io::Read,
+    {
+        car::import(&self.store, r)
+            .await
+            .map_err(|err| Error::Other(anyhow!(err)))
+    }
 }
 impl Fixity<Memory, Memory> {
     /// Construct a new, **in memory only** instance
@@ -97,13 +157,10 @@ where
         meta: Arc<M>,
         store: Arc<S>,
         repo: &str,
-        rid: Rid,
+        key: ReplicaKey,
     ) -> Result<Self, Error> {
-        let log = match meta.head("local", &rid).await {
-            Ok(log_tip) => ReplicaLog::open(&store, &log_tip).await.unwrap(),
-            Err(MetaStoreError::NotFound) => ReplicaLog::default_container(&store),
-            Err(err) => return Err(Error::Other(anyhow!(err))),
-        };
+        let rid = key.rid();
+        let log = open_log(meta.as_ref(), &store, key).await?;
         let (container, new) = match log.repo_tip(repo) {
             Some(tip) => (T::open(&store, &tip).await.unwrap(), false),
             None => (T::default_container(&store), true),
@@ -145,8 +202,123 @@ where
         self.clean = true;
         Ok(container_tip)
     }
+    /// Merge the repo of every other replica of the same identity as this replica into this
+    /// replica.
+    ///
+    /// The merged changes are not committed.
+    pub async fn merge_identity(&mut self) -> Result<(), Error> {
+        let replicas = identity_replicas(self.meta.as_ref(), &self.store, self.replica_id).await?;
+        let own_tip = self.tip();
+        for rid in replicas {
+            if rid == self.replica_id {
+                continue;
+            }
+            let log = match open_replica_log(self.meta.as_ref(), &self.store, rid).await? {
+                Some(log) => log,
+                None => continue,
+            };
+            let tip = match log.repo_tip(&self.repo) {
+                Some(tip) if Some(tip) != own_tip => tip,
+                _ => continue,
+            };
+            self.container
+                .merge(&self.store, &tip)
+                .await
+                .map_err(|err| Error::Other(anyhow!(err)))?;
+            self.clean = false;
+        }
+        Ok(())
+    }
+    /// Export the committed DAG of the associated `Repo` as a CARv1, rooted at [`Self::tip`].
+    ///
+    /// Uncommitted changes are not included.
+    pub async fn export_car<W>(&self, w: W) -> Result<(), Error>
+    where
+        W: std::io::Write,
+    {
+        let roots = self.tip().into_iter().collect::<Vec<_>>();
+        car::export(&self.store, &roots, w)
+            .await
+            .map_err(|err| Error::Other(anyhow!(err)))
+    }
 }
 impl<M, S, T> RepoReplica<M, S, T> {}
+/// Open the log of the replica of the given key, or a new log if the replica has no log yet.
+async fn open_log<M, S>(meta: &M, store: &Arc<S>, key: ReplicaKey) -> Result<ReplicaLog<S>, Error>
+where
+    M: MetaStore,
+    S: ContentStore,
+{
+    let log = match open_replica_log(meta, store, key.rid()).await? {
+        Some(mut log) => {
+            log.set_key(key).map_err(|err| Error::Other(anyhow!(err)))?;
+            log
+        },
+        None => ReplicaLog::with_key(store, key),
+    };
+    Ok(log)
+}
+/// Open and verify the log of the given replica, if the replica has a log.
+async fn open_replica_log<M, S>(
+    meta: &M,
+    store: &Arc<S>,
+    rid: Rid,
+) -> Result<Option<ReplicaLog<S>>, Error>
+where
+    M: MetaStore,
+    S: ContentStore,
+{
+    match meta.head("local", &rid).await {
+        Ok(log_tip) => ReplicaLog::open_replica(store, &log_tip, rid)
+            .await
+            .map(Some)
+            .map_err(|err| Error::Other(anyhow!(err))),
+        Err(MetaStoreError::NotFound) => Ok(None),
+        Err(err) => Err(Error::Other(anyhow!(err))),
+    }
+}
+/// Resolve all replicas of the identity that `rid` belongs to, including `rid` itself.
+///
+/// A verified claim links the two replicas in both directions, as both replicas have signed it.
+/// The identity is every replica reachable from `rid` over those links.
+//
+// PERF: This opens the identity of every known replica. Fine for a handful of devices, but an
+// index of claims will be needed eventually.
+async fn identity_replicas<M, S>(meta: &M, store: &Arc<S>, rid: Rid) -> Result<Vec<Rid>, Error>
+where
+    M: MetaStore,
+    S: ContentStore,
+{
+    let mut links = BTreeMap::<Rid, BTreeSet<Rid>>::new();
+    let replicas = meta
+        .replicas("local")
+        .await
+        .map_err(|err| Error::Other(anyhow!(err)))?;
+    for claimer in replicas {
+        let log = match open_replica_log(meta, store, claimer).await? {
+            Some(log) => log,
+            None => continue,
+        };
+        let identity = log
+            .identity(store)
+            .await
+            .map_err(|err| Error::Other(anyhow!(err)))?;
+        for claimed in identity.verified_replicas(&claimer) {
+            links.entry(claimer).or_default().insert(claimed);
+            links.entry(claimed).or_default().insert(claimer);
+        }
+    }
+    let mut found = BTreeSet::from([rid]);
+    let mut queue = VecDeque::from([rid]);
+    while let Some(rid) = queue.pop_front() {
+        for linked in links.get(&rid).into_iter().flatten() {
+            if found.insert(*linked) {
+                queue.push_back(*linked);
+            }
+        }
+    }
+    Ok(found.into_iter().collect())
+}
 impl<M, S, T> Deref for RepoReplica<M, S, T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
@@ -165,20 +337,19 @@ pub mod test {
 
     #[tokio::test]
     async fn basic_mutation() {
-        use fixity_store::replicaid::Rid;
-        let rid = Rid::default();
+        let key = ReplicaKey::from_seed([0; 32]);
         let repo_name = "repo name";
         let fixi = Fixity::memory();
         // TODO: Snapshot Cids, maybe?
         let cida = {
-            let mut repo = fixi.open::<String>(repo_name, rid).await.unwrap();
+            let mut repo = fixi.open::<String>(repo_name, &key).await.unwrap();
             let t = repo.deref_mut();
             *t = String::from("foo");
             let cida = repo.commit().await.unwrap();
             dbg!(cida)
         };
         let cidb = {
-            let mut repo = fixi.open::<String>(repo_name, rid).await.unwrap();
+            let mut repo = fixi.open::<String>(repo_name, &key).await.unwrap();
             let t = repo.deref_mut();
             assert_eq!(t, "foo");
             *t = String::from("bar");
@@ -187,11 +358,58 @@ pub mod test {
         };
         assert_ne!(cida, cidb, "different content should have a different cid");
 
-        let mut repo = fixi.open::<String>(repo_name, rid).await.unwrap();
+        let mut repo = fixi.open::<String>(repo_name, &key).await.unwrap();
         let t = repo.deref_mut();
         assert_eq!(t, "bar");
         *t = String::from("foo");
         let cidc = repo.commit().await.unwrap();
         assert_eq!(cida, cidc, "same content should have the same cid");
     }
+    #[tokio::test]
+    async fn car_roundtrip() {
+        let key = ReplicaKey::from_seed([0; 32]);
+        let repo_name = "repo name";
+        let fixi = Fixity::memory();
+        let mut repo = fixi.open::<String>(repo_name, &key).await.unwrap();
+        *repo.deref_mut() = String::from("foo");
+        let cid = repo.commit().await.unwrap();
+        let mut car = Vec::new();
+        repo.export_car(&mut car).await.unwrap();
+        let other = Fixity::memory();
+        assert_eq!(other.import_car(car.as_slice()).await.unwrap(), vec![cid]);
+        let value = String::open(&other.store, &cid).await.unwrap();
+        assert_eq!(value, "foo");
+    }
+    #[tokio::test]
+    async fn identity() {
+        use fixity_structs::{gcounter::GCounter, replicalog::ack_claim};
+        let (a, b, c) = (
+            ReplicaKey::from_seed([1; 32]),
+            ReplicaKey::from_seed([2; 32]),
+            ReplicaKey::from_seed([3; 32]),
+        );
+        let repo_name = "repo name";
+        let fixi = Fixity::memory();
+        for key in [&a, &b, &c] {
+            let mut repo = fixi.open::<GCounter>(repo_name, key).await.unwrap();
+            repo.inc(key.rid());
+            repo.commit().await.unwrap();
+        }
+        fixi.claim_replica(&a, b.rid(), ack_claim(&b, &a.rid()))
+            .await
+            .unwrap();
+        let mut ab = vec![a.rid(), b.rid()];
+        ab.sort();
+        assert_eq!(fixi.identity_replicas(a.rid()).await.unwrap(), ab);
+        assert_eq!(fixi.identity_replicas(b.rid()).await.unwrap(), ab);
+        assert_eq!(
+            fixi.identity_replicas(c.rid()).await.unwrap(),
+            vec![c.rid()]
+        );
+        let repo = fixi.open_identity::<GCounter>(repo_name, &b).await.unwrap();
+        assert_eq!(repo.value(), 2, "merged a, but not c");
+        // The claim did not disturb the repo of a.
+        let repo = fixi.open::<GCounter>(repo_name, &a).await.unwrap();
+        assert_eq!(repo.value(), 1);
+    }
 }
@@ -0,0 +1,54 @@
+use {
+    criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput},
+    fbuzhash::{
+        chunker::{BuzHashChunker, Chunker},
+        fastcdc::FastCdc,
+        test_util::bytes,
+        BuzHash,
+    },
+};
+const WINDOW_SIZE: u32 = 64;
+const PATTERN: u32 = (1 << 13) - 1;
+fn boundaries(c: &mut Criterion) {
+    let mut group = c.benchmark_group("boundaries");
+    for len in [64 * 1024, 4 * 1024 * 1024].iter() {
+        let buf = bytes(*len, 1);
+        group.throughput(Throughput::Bytes(*len as u64));
+        group.bench_with_input(BenchmarkId::new("hash_byte", len), &buf, |b, buf| {
+            b.iter(|| {
+                let mut hash = BuzHash::new(WINDOW_SIZE);
+                buf.iter()
+                    .filter(|&&b| hash.hash_byte(b) & PATTERN == PATTERN)
+                    .count()
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("find_boundary", len), &buf, |b, buf| {
+            b.iter(|| {
+                let mut hash = BuzHash::new(WINDOW_SIZE);
+                let (mut buf, mut count) = (buf.as_slice(), 0);
+                while let Some(boundary) = hash.find_boundary(buf, PATTERN) {
+                    buf = &buf[boundary..];
+                    count += 1;
+                }
+                count
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("buzhash_chunker", len), &buf, |b, buf| {
+            b.iter(|| {
+                BuzHashChunker::new(WINDOW_SIZE, PATTERN, 2 * 1024, 64 * 1024)
+                    .chunks(buf)
+                    .count()
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("fastcdc", len), &buf, |b, buf| {
+            b.iter(|| {
+                FastCdc::new(2 * 1024, 8 * 1024, 64 * 1024)
+                    .chunks(buf)
+                    .count()
+            });
+        });
+    }
+    group.finish();
+}
+criterion_group!(benches, boundaries);
+criterion_main!(benches);
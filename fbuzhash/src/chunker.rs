@@ -0,0 +1,280 @@
+//! Content-defined chunking, splitting a stream of bytes at boundaries defined by the bytes
+//! themselves such that an edit only changes the chunks around it.
+use crate::BuzHash;
+
+/// A content-defined chunker, fed the bytes of a stream in order.
+pub trait Chunker {
+    /// Scan `buf` as the continuation of the current chunk, returning the number of bytes of
+    /// `buf` ending the chunk, or `None` if every byte of `buf` belongs to the current chunk.
+    ///
+    /// Once a boundary is returned, the chunker is [reset](Chunker::reset) and the remaining bytes
+    /// of `buf` begin the next chunk.
+    fn next_boundary(&mut self, buf: &[u8]) -> Option<usize>;
+    /// Discard the current chunk, such that the next byte begins a new chunk.
+    fn reset(&mut self);
+    /// Split `buf` into chunks, starting with a new chunk.
+    ///
+    /// The last chunk is ended by the end of `buf` rather than a boundary.
+    fn chunks<'a>(&'a mut self, buf: &'a [u8]) -> Chunks<'a, Self>
+    where
+        Self: Sized,
+    {
+        self.reset();
+        Chunks { chunker: self, buf }
+    }
+}
+/// An iterator over the chunks of a buffer, see [`Chunker::chunks`].
+pub struct Chunks<'a, C> {
+    chunker: &'a mut C,
+    buf: &'a [u8],
+}
+impl<'a, C: Chunker> Iterator for Chunks<'a, C> {
+    type Item = &'a [u8];
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        let len = self
+            .chunker
+            .next_boundary(self.buf)
+            .unwrap_or(self.buf.len());
+        let (chunk, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Some(chunk)
+    }
+}
+/// A [`Chunker`] ending chunks where the [`BuzHash`] of the bytes matches a single pattern,
+/// bounded by a minimum and maximum chunk size.
+///
+/// Between the bounds, chunk sizes follow a geometric distribution. See
+/// [`FastCdc`](crate::fastcdc::FastCdc) for a narrower distribution.
+pub struct BuzHashChunker {
+    buzhash: BuzHash,
+    pattern: u32,
+    min: usize,
+    max: usize,
+    /// The length of the current chunk.
+    len: usize,
+}
+impl BuzHashChunker {
+    /// Construct a chunker with the given BuzHash window size, ending chunks where the hash
+    /// truncated by `pattern` equals `pattern`.
+    ///
+    /// Typically `pattern` is a series of all `1` bits, where a width of `n` bits produces an
+    /// average chunk size of `2^n` bytes, before the bounds.
+    pub fn new(window_size: u32, pattern: u32, min: usize, max: usize) -> Self {
+        assert!(
+            0 < max && min <= max,
+            "chunk sizes must be ordered as min <= max, with max above 0"
+        );
+        Self {
+            buzhash: BuzHash::new(window_size),
+            pattern,
+            min,
+            max,
+            len: 0,
+        }
+    }
+    /// Hash with a table derived from a secret key, see [`keyed_table`](crate::keyed_table).
+    pub fn with_key(mut self, key: &[u8; 32]) -> Self {
+        self.buzhash = BuzHash::with_key(self.buzhash.window_size(), key);
+        self
+    }
+}
+impl Chunker for BuzHashChunker {
+    fn next_boundary(&mut self, buf: &[u8]) -> Option<usize> {
+        // Bytes before the minimum chunk size are hashed, but cannot end the chunk.
+        let head = (self.min.saturating_sub(1))
+            .saturating_sub(self.len)
+            .min(buf.len());
+        self.buzhash.hash_bytes(&buf[..head]);
+        self.len += head;
+        let tail = (self.max - self.len).min(buf.len() - head);
+        match self
+            .buzhash
+            .find_boundary(&buf[head..head + tail], self.pattern)
+        {
+            Some(boundary) => {
+                self.reset();
+                Some(head + boundary)
+            },
+            None if self.len + tail >= self.max => {
+                self.reset();
+                Some(head + tail)
+            },
+            None => {
+                self.len += tail;
+                None
+            },
+        }
+    }
+    fn reset(&mut self) {
+        self.buzhash.reset();
+        self.len = 0;
+    }
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::{fastcdc::FastCdc, test_util::bytes};
+
+    const MIN: usize = 1024;
+    const AVG: usize = 4 * 1024;
+    const MAX: usize = 16 * 1024;
+
+    fn buzhash() -> BuzHashChunker {
+        BuzHashChunker::new(64, (AVG as u32) - 1, MIN, MAX)
+    }
+    fn chunk_lens<C: Chunker>(mut chunker: C, buf: &[u8]) -> Vec<usize> {
+        chunker.chunks(buf).map(<[u8]>::len).collect()
+    }
+    fn mean_stddev(lens: &[usize]) -> (f64, f64) {
+        let mean = lens.iter().sum::<usize>() as f64 / lens.len() as f64;
+        let variance = lens
+            .iter()
+            .map(|&len| (len as f64 - mean).powi(2))
+            .sum::<f64>()
+            / lens.len() as f64;
+        (mean, variance.sqrt())
+    }
+    /// Feed `buf` to the chunker in small, uneven reads.
+    fn streamed_lens<C: Chunker>(mut chunker: C, buf: &[u8]) -> Vec<usize> {
+        let (mut lens, mut len) = (Vec::new(), 0);
+        for read in buf.chunks(1000) {
+            let mut read = read;
+            while let Some(boundary) = chunker.next_boundary(read) {
+                lens.push(len + boundary);
+                len = 0;
+                read = &read[boundary..];
+            }
+            len += read.len();
+        }
+        lens.push(len);
+        lens
+    }
+    #[test]
+    fn streaming_matches_whole() {
+        let buf = bytes(256 * 1024, 1);
+        let whole = chunk_lens(FastCdc::new(MIN, AVG, MAX), &buf);
+        assert_eq!(streamed_lens(FastCdc::new(MIN, AVG, MAX), &buf), whole);
+        assert_eq!(whole.iter().sum::<usize>(), buf.len());
+        let whole = chunk_lens(buzhash(), &buf);
+        assert_eq!(streamed_lens(buzhash(), &buf), whole);
+        // Every chunk matches the pattern, or is bounded by the minimum or maximum size.
+        let mut hash = BuzHash::new(64);
+        let pattern = AVG as u32 - 1;
+        let mut expected = (Vec::new(), 0);
+        for &b in buf.iter() {
+            expected.1 += 1;
+            let matched = hash.hash_byte(b) & pattern == pattern;
+            if (matched && expected.1 >= MIN) || expected.1 >= MAX {
+                expected.0.push(expected.1);
+                expected.1 = 0;
+                hash.reset();
+            }
+        }
+        expected.0.push(expected.1);
+        assert_eq!(whole, expected.0);
+    }
+    #[test]
+    fn histogram() {
+        let buf = bytes(4 * 1024 * 1024, 2);
+        let buz = chunk_lens(buzhash(), &buf);
+        let unnormalized = chunk_lens(FastCdc::new(MIN, AVG, MAX).with_normalization(0), &buf);
+        let normalized = chunk_lens(FastCdc::new(MIN, AVG, MAX), &buf);
+        for lens in [&buz, &unnormalized, &normalized].iter() {
+            let (last, lens) = lens.split_last().unwrap();
+            assert!(*last <= MAX);
+            assert!(lens.iter().all(|len| (MIN..=MAX).contains(len)));
+        }
+        let (buz_mean, buz_stddev) = mean_stddev(&buz);
+        let (mean, stddev) = mean_stddev(&normalized);
+        let (_, unnormalized_stddev) = mean_stddev(&unnormalized);
+        assert!(
+            (mean - AVG as f64).abs() < AVG as f64 * 0.25,
+            "mean {} too far from {}",
+            mean,
+            AVG
+        );
+        assert!(
+            stddev < unnormalized_stddev && stddev < buz_stddev,
+            "normalized stddev {} not below unnormalized {} and buzhash {} (mean {})",
+            stddev,
+            unnormalized_stddev,
+            buz_stddev,
+            buz_mean,
+        );
+    }
+    /// The fraction of bytes of `edited` within chunks shared with `original`.
+    fn dedup_ratio<C: Chunker>(
+        mut new_chunker: impl FnMut() -> C,
+        original: &[u8],
+        edited: &[u8],
+    ) -> f64 {
+        let mut chunker = new_chunker();
+        let original = chunker
+            .chunks(original)
+            .collect::<std::collections::HashSet<_>>();
+        let mut chunker = new_chunker();
+        let shared = chunker
+            .chunks(edited)
+            .filter(|chunk| original.contains(chunk))
+            .map(<[u8]>::len)
+            .sum::<usize>();
+        shared as f64 / edited.len() as f64
+    }
+    #[test]
+    fn dedup_shifted() {
+        let original = bytes(1024 * 1024, 3);
+        for shift in [1, 7, 100, 5_000].iter() {
+            let mut shifted = bytes(*shift, 4);
+            shifted.extend_from_slice(&original);
+            let mut edited = original.clone();
+            edited.splice(500_000..500_000, bytes(*shift, 5));
+            for edited in [shifted, edited].iter() {
+                let buz = dedup_ratio(buzhash, &original, edited);
+                let fastcdc = dedup_ratio(|| FastCdc::new(MIN, AVG, MAX), &original, edited);
+                assert!(
+                    buz > 0.95,
+                    "buzhash dedup ratio {} with shift {}",
+                    buz,
+                    shift
+                );
+                assert!(
+                    fastcdc > 0.95,
+                    "fastcdc dedup ratio {} with shift {}",
+                    fastcdc,
+                    shift
+                );
+            }
+        }
+    }
+    #[test]
+    fn keyed() {
+        let original = bytes(1024 * 1024, 6);
+        let mut edited = original.clone();
+        edited.splice(500_000..500_000, bytes(100, 7));
+        let (key, other_key) = ([1; 32], [2; 32]);
+        let keyed = || buzhash().with_key(&key);
+        let keyed_lens = chunk_lens(keyed(), &original);
+        assert_eq!(chunk_lens(keyed(), &original), keyed_lens);
+        assert_ne!(chunk_lens(buzhash(), &original), keyed_lens);
+        assert_ne!(
+            chunk_lens(buzhash().with_key(&other_key), &original),
+            keyed_lens
+        );
+        assert!(dedup_ratio(keyed, &original, &edited) > 0.95);
+        let keyed = || FastCdc::new(MIN, AVG, MAX).with_key(&key);
+        let keyed_lens = chunk_lens(keyed(), &original);
+        assert_eq!(chunk_lens(keyed(), &original), keyed_lens);
+        assert_ne!(
+            chunk_lens(FastCdc::new(MIN, AVG, MAX), &original),
+            keyed_lens
+        );
+        assert_ne!(
+            chunk_lens(FastCdc::new(MIN, AVG, MAX).with_key(&other_key), &original),
+            keyed_lens
+        );
+        assert!(dedup_ratio(keyed, &original, &edited) > 0.95);
+    }
+}
@@ -0,0 +1,174 @@
+//! A Gear hash based chunker, following FastCDC.
+//!
+//! Reference: "FastCDC: a Fast and Efficient Content-Defined Chunking Approach for Data
+//! Deduplication", Xia et al, USENIX ATC 2016.
+use crate::chunker::Chunker;
+
+/// The seed of [`GEAR`], fixed such that the same content always produces the same chunks.
+const GEAR_SEED: u64 = 0x6669_7869_7479_6364;
+/// The default level of normalized chunking, see [`FastCdc::with_normalization`].
+const DEFAULT_NORMALIZATION: u32 = 2;
+
+/// 256 pseudo random uint64's to hash a single byte.
+const GEAR: [u64; 256] = gear_table(GEAR_SEED);
+
+/// Derive a Gear table from a secret key, see [`keyed_table`](crate::keyed_table).
+pub fn keyed_gear_table(key: &[u8; 32]) -> [u64; 256] {
+    let mut bytes = [0; 256 * 8];
+    blake3::Hasher::new_keyed(key)
+        .update(b"fbuzhash gear table")
+        .finalize_xof()
+        .fill(&mut bytes);
+    let mut table = [0; 256];
+    for (entry, bytes) in table.iter_mut().zip(bytes.chunks_exact(8)) {
+        let mut le = [0; 8];
+        le.copy_from_slice(bytes);
+        *entry = u64::from_le_bytes(le);
+    }
+    table
+}
+/// Fill a Gear table from the given seed, with SplitMix64.
+const fn gear_table(seed: u64) -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state = seed;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+/// A [`Chunker`] ending chunks where the Gear hash of the bytes has the masked bits unset, bounded
+/// by a minimum and maximum chunk size.
+///
+/// Bytes within the minimum chunk size are not hashed at all, and with normalized chunking a
+/// harder mask is used before the average chunk size and an easier mask after, narrowing the
+/// distribution of chunk sizes around the average.
+pub struct FastCdc {
+    gear: [u64; 256],
+    min: usize,
+    avg: usize,
+    max: usize,
+    /// The mask used before the average chunk size.
+    mask_s: u64,
+    /// The mask used from the average chunk size onward.
+    mask_l: u64,
+    hash: u64,
+    /// The length of the current chunk.
+    len: usize,
+}
+impl FastCdc {
+    /// Construct a chunker targeting an `avg` chunk size, with the default normalization.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sizes are not ordered as `min <= avg <= max`.
+    pub fn new(min: usize, avg: usize, max: usize) -> Self {
+        assert!(
+            min <= avg && avg <= max,
+            "chunk sizes must be ordered as min <= avg <= max"
+        );
+        let mut chunker = Self {
+            gear: GEAR,
+            min,
+            avg,
+            max,
+            mask_s: 0,
+            mask_l: 0,
+            hash: 0,
+            len: 0,
+        };
+        chunker.set_normalization(DEFAULT_NORMALIZATION);
+        chunker
+    }
+    /// Set the level of normalized chunking, where the mask before the average chunk size has
+    /// `level` more bits than the average implies, and the mask after has `level` fewer bits.
+    ///
+    /// A level of `0` disables normalization, and the FastCDC paper suggests `1` to `3`.
+    pub fn with_normalization(mut self, level: u32) -> Self {
+        self.set_normalization(level);
+        self
+    }
+    /// Hash with a Gear table derived from a secret key, see [`keyed_gear_table`].
+    pub fn with_key(mut self, key: &[u8; 32]) -> Self {
+        self.gear = keyed_gear_table(key);
+        self
+    }
+    fn set_normalization(&mut self, level: u32) {
+        let bits = log2(self.avg as u64);
+        self.mask_s = mask(bits + level);
+        self.mask_l = mask(bits.saturating_sub(level));
+    }
+}
+/// The base 2 logarithm, rounded to the nearest integer.
+fn log2(n: u64) -> u32 {
+    let n = n.max(1);
+    let floor = 63 - n.leading_zeros();
+    // Round up if `n` is closer to the next power of two.
+    if floor < 63 && (n - (1 << floor)) * 2 >= 1 << floor {
+        floor + 1
+    } else {
+        floor
+    }
+}
+/// A mask of the given number of bits.
+///
+/// The highest bits are used, as each step of the Gear hash shifts older bytes toward them. The
+/// lowest bits would only depend on the last few bytes.
+fn mask(bits: u32) -> u64 {
+    match bits.min(64) {
+        0 => 0,
+        bits => u64::MAX << (64 - bits),
+    }
+}
+impl Chunker for FastCdc {
+    fn next_boundary(&mut self, buf: &[u8]) -> Option<usize> {
+        // Skip hashing the bytes within the minimum chunk size, the hash only depends on the last
+        // 64 bytes regardless.
+        let skip = self.min.saturating_sub(self.len).min(buf.len());
+        self.len += skip;
+        for (i, &b) in buf.iter().enumerate().skip(skip) {
+            self.len += 1;
+            self.hash = (self.hash << 1).wrapping_add(self.gear[b as usize]);
+            let mask = if self.len < self.avg {
+                self.mask_s
+            } else {
+                self.mask_l
+            };
+            if self.hash & mask == 0 || self.len >= self.max {
+                self.reset();
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+    fn reset(&mut self) {
+        self.hash = 0;
+        self.len = 0;
+    }
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    #[test]
+    fn masks() {
+        assert_eq!(log2(4096), 12);
+        assert_eq!(log2(5000), 12);
+        assert_eq!(log2(7000), 13);
+        assert_eq!(log2(0), 0);
+        assert_eq!(log2(1), 0);
+        assert_eq!(log2(3), 2);
+        // Degenerate sizes end a chunk at every byte.
+        let mut chunker = FastCdc::new(0, 0, 0);
+        assert_eq!(chunker.next_boundary(&[1, 2, 3]), Some(1));
+        let chunker = FastCdc::new(1024, 8192, 65536);
+        assert_eq!(chunker.mask_s.count_ones(), 15);
+        assert_eq!(chunker.mask_l.count_ones(), 11);
+        let chunker = chunker.with_normalization(0);
+        assert_eq!(chunker.mask_s, chunker.mask_l);
+    }
+}
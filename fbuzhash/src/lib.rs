@@ -6,6 +6,11 @@
 //!
 //! The reference implementation has a [LICENSE](https://github.com/silvasur/buzhash/blob/master/LICENSE)
 //! that i'm not exactly sure what to do with.
+pub mod chunker;
+pub mod fastcdc;
+#[cfg(any(test, feature = "test"))]
+pub mod test_util;
+
 use std::io::{self, Write};
 
 /// 256 random uint32's to hash a single byte.
@@ -44,8 +49,26 @@ const BYTE_HASH: [u32; 256] = [
     0x6a68ccfd, 0x62529f0b, 0xec5f36d6, 0x766cceda, 0x96ca63ef, 0xa0499838, 0xd9030f59, 0x8185f4d2,
 ];
 
+/// Derive a table of 256 uint32's to hash a single byte from a secret key.
+///
+/// With the fixed byte table of [`BuzHash::new`], anyone able to see chunk sizes can test whether known
+/// content was chunked. Hashes sharing a key produce the same boundaries as each other, still
+/// deduplicating content, while the boundaries reveal nothing to those without the key.
+pub fn keyed_table(key: &[u8; 32]) -> [u32; 256] {
+    let mut bytes = [0; 256 * 4];
+    blake3::Hasher::new_keyed(key)
+        .update(b"fbuzhash byte table")
+        .finalize_xof()
+        .fill(&mut bytes);
+    let mut table = [0; 256];
+    for (entry, bytes) in table.iter_mut().zip(bytes.chunks_exact(4)) {
+        *entry = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    table
+}
 /// BuzHash implements the hash.Hash32 interface and also has a function to write a single byte.
 pub struct BuzHash {
+    table: [u32; 256],
     state: u32,
     buf: Vec<u8>,
     n: u32,
@@ -56,9 +79,18 @@ pub struct BuzHash {
 }
 impl BuzHash {
     pub fn new(n: u32) -> Self {
+        Self::with_table(n, BYTE_HASH)
+    }
+    /// Construct a hash with a table derived from a secret key, see [`keyed_table`].
+    pub fn with_key(n: u32, key: &[u8; 32]) -> Self {
+        Self::with_table(n, keyed_table(key))
+    }
+    /// Construct a hash with the given table of 256 random uint32's to hash a single byte.
+    pub fn with_table(n: u32, table: [u32; 256]) -> Self {
         let bshiftn = n % 32;
         let bshiftm = 32 - bshiftn;
         Self {
+            table,
             state: 0,
             buf: vec![0u8; n as usize],
             n,
@@ -76,10 +108,10 @@ impl BuzHash {
         }
 
         let mut state = self.state;
-        state = (state << 1) | (state >> 31);
+        state = state.rotate_left(1);
 
         if self.overflow {
-            let toshift = BYTE_HASH[self.buf[self.bufpos] as usize];
+            let toshift = self.table[self.buf[self.bufpos] as usize];
             // I believe the assumption here is that the toshift always comes from
             // BYTE_HASH, and thus left shifting via bshiftn can't overflow if all
             // the values within BYTE_HASH are safe. Not positive.
@@ -98,11 +130,64 @@ impl BuzHash {
         self.buf[self.bufpos] = b;
         self.bufpos += 1;
 
-        state ^= BYTE_HASH[b as usize];
+        state ^= self.table[b as usize];
 
         self.state = state;
         state
     }
+    /// Hash the bytes of `buf` in order, returning the number of bytes hashed up to and including
+    /// the first byte where the hash truncated by `pattern` equals `pattern`, if any.
+    ///
+    /// Bytes after the boundary are not hashed. The hash is otherwise identical to calling
+    /// [`Self::hash_byte`] for each byte, but once the window is filled from `buf` the outgoing
+    /// bytes are read from `buf` directly, rather than through the window buffer.
+    pub fn find_boundary(&mut self, buf: &[u8], pattern: u32) -> Option<usize> {
+        self.roll(buf, Some(pattern))
+    }
+    /// Hash the bytes of `buf` in order, like [`Self::find_boundary`] but without looking for a
+    /// boundary.
+    pub fn hash_bytes(&mut self, buf: &[u8]) -> u32 {
+        self.roll(buf, None);
+        self.state
+    }
+    fn roll(&mut self, buf: &[u8], pattern: Option<u32>) -> Option<usize> {
+        let n = self.n as usize;
+        let matches = |state: u32| matches!(pattern, Some(pattern) if state & pattern == pattern);
+        // Until the window is filled from `buf`, outgoing bytes come from the window buffer.
+        let head = n.min(buf.len());
+        for (i, &b) in buf[..head].iter().enumerate() {
+            if matches(self.hash_byte(b)) {
+                return Some(i + 1);
+            }
+        }
+        if head == buf.len() {
+            return None;
+        }
+        let mut state = self.state;
+        let mut end = buf.len();
+        let mut boundary = None;
+        for (i, (&b, &outgoing)) in buf[n..].iter().zip(buf).enumerate() {
+            // Equivalent to the shifts of `hash_byte`, where a shift of `bshiftm == 32` is `0`.
+            state = state.rotate_left(1)
+                ^ self.table[outgoing as usize].rotate_left(self.bshiftn)
+                ^ self.table[b as usize];
+            if matches(state) {
+                end = n + i + 1;
+                boundary = Some(end);
+                break;
+            }
+        }
+        self.state = state;
+        // Leave the window buffer as `hash_byte` would, full with the oldest byte next.
+        self.buf.copy_from_slice(&buf[end - n..end]);
+        self.bufpos = n;
+        self.overflow = true;
+        boundary
+    }
+    /// The number of bytes within the rolling window.
+    pub fn window_size(&self) -> u32 {
+        self.n
+    }
     pub fn sum32(&self) -> u32 {
         self.state
     }
@@ -114,9 +199,7 @@ impl BuzHash {
 }
 impl Write for BuzHash {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        for &b in buf {
-            let _ = self.hash_byte(b);
-        }
+        self.hash_bytes(buf);
         Ok(buf.len())
     }
     fn flush(&mut self) -> io::Result<()> {
@@ -202,4 +285,37 @@ pede sit amet augue. In turpis. Pellentesque posuere. Praesent turpis.";
         }
         assert!(found, "phrase2 sum not found within loremipsum2");
     }
+    #[test]
+    fn find_boundary_matches_hash_byte() {
+        let bytes = test_util::bytes(20_000, 1);
+        let pattern = (1 << 8) - 1;
+        for &window in [1, 31, 32, 33, 64, 67].iter() {
+            for &read_size in [1, 10, 67, 1_000, 20_000].iter() {
+                let (mut expected, mut found) = (BuzHash::new(window), BuzHash::new(window));
+                let (mut expected_boundaries, mut boundaries) = (Vec::new(), Vec::new());
+                for (i, &b) in bytes.iter().enumerate() {
+                    if expected.hash_byte(b) & pattern == pattern {
+                        expected_boundaries.push(i + 1);
+                    }
+                }
+                let mut offset = 0;
+                for read in bytes.chunks(read_size) {
+                    let mut read = read;
+                    while let Some(boundary) = found.find_boundary(read, pattern) {
+                        offset += boundary;
+                        boundaries.push(offset);
+                        read = &read[boundary..];
+                    }
+                    offset += read.len();
+                }
+                assert_eq!(boundaries, expected_boundaries);
+                assert_eq!(found.sum32(), expected.sum32());
+                let mut hashed = BuzHash::new(window);
+                for read in bytes.chunks(read_size) {
+                    hashed.hash_bytes(read);
+                }
+                assert_eq!(hashed.sum32(), expected.sum32());
+            }
+        }
+    }
 }
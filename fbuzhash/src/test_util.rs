@@ -0,0 +1,15 @@
+//! Test focused helpers, shared with the tests and benches of dependent crates.
+
+/// Pseudo random bytes from a seed, standing in for real content such that chunk boundaries are
+/// spread as they would be in practice.
+pub fn bytes(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 56) as u8
+        })
+        .collect()
+}
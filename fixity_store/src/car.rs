@@ -0,0 +1,323 @@
+//! Import and export of [CARv1](https://ipld.io/specs/transport/car/carv1/) files, allowing
+//! fixity DAGs to be moved to and from IPFS tooling while preserving Cids.
+use crate::{
+    content_store::{ContentStore, ContentStoreError},
+    contentid::{varint, Cid, Codec, ContentId, FromHashError, MULTIHASH_PREFIX},
+};
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+};
+use thiserror::Error;
+
+/// The CARv1 header version.
+const CAR_VERSION: u64 = 1;
+/// The CBOR tag for IPLD links.
+const CBOR_TAG_CID: u64 = 42;
+const CBOR_MAJOR_UINT: u8 = 0;
+const CBOR_MAJOR_BYTES: u8 = 2;
+const CBOR_MAJOR_TEXT: u8 = 3;
+const CBOR_MAJOR_ARRAY: u8 = 4;
+const CBOR_MAJOR_MAP: u8 = 5;
+const CBOR_MAJOR_TAG: u8 = 6;
+
+#[derive(Error, Debug)]
+pub enum CarError {
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("store: {0}")]
+    ContentStore(#[from] ContentStoreError),
+    #[error("invalid car header: {message}")]
+    Header { message: String },
+    #[error("invalid cid: {0}")]
+    Cid(#[from] FromHashError),
+    #[error("block data does not match cid: {cid}")]
+    HashMismatch { cid: Cid },
+    #[error("section length exceeds the car")]
+    SectionLength,
+}
+/// Export the DAG under each of the given `roots` as a CARv1, writing it to `w`.
+///
+/// Every block reachable from the roots is written exactly once, in depth first order starting
+/// from the roots.
+///
+/// Every block is tagged [`Codec::FixityRkyv`], as blocks are always written serialized rather
+/// than as raw bytes, blob chunks included. Other IPFS tooling can store and transfer these
+/// blocks, but not decode them.
+pub async fn export<S, W>(store: &S, roots: &[Cid], mut w: W) -> Result<(), CarError>
+where
+    S: ContentStore,
+    W: Write,
+{
+    let mut header = Vec::new();
+    cbor_head(CBOR_MAJOR_MAP, 2, &mut header);
+    cbor_text("roots", &mut header);
+    cbor_head(CBOR_MAJOR_ARRAY, roots.len() as u64, &mut header);
+    for root in roots {
+        // NIT: Roots are tagged as rkyv blocks, as we have no way to know the codec of a block.
+        // Same for the blocks below.
+        let cid = root.to_cid_v1(Codec::FixityRkyv);
+        cbor_head(CBOR_MAJOR_TAG, CBOR_TAG_CID, &mut header);
+        // IPLD links in CBOR are prefixed with the multibase identity prefix.
+        cbor_head(CBOR_MAJOR_BYTES, cid.len() as u64 + 1, &mut header);
+        header.push(0);
+        header.extend_from_slice(&cid);
+    }
+    cbor_text("version", &mut header);
+    cbor_head(CBOR_MAJOR_UINT, CAR_VERSION, &mut header);
+    write_section(&mut w, &[&header])?;
+    let mut seen = HashSet::new();
+    let mut stack = roots.iter().rev().copied().collect::<Vec<_>>();
+    while let Some(cid) = stack.pop() {
+        if !seen.insert(cid) {
+            continue;
+        }
+        let bytes = store.read_unchecked(&cid).await?;
+        let bytes = bytes.as_ref();
+        let children = linked_cids(store, bytes).await?;
+        stack.extend(children.into_iter().rev().filter(|cid| !seen.contains(cid)));
+        write_section(&mut w, &[&cid.to_cid_v1(Codec::FixityRkyv), bytes])?;
+    }
+    w.flush()?;
+    Ok(())
+}
+/// Import every block in the CARv1 read from `r` into the given `store`, returning the roots of
+/// the CAR.
+///
+/// Each block is verified against its Cid before being written.
+pub async fn import<S, R>(store: &S, mut r: R) -> Result<Vec<Cid>, CarError>
+where
+    S: ContentStore,
+    R: Read,
+{
+    // PERF: Reading the entire CAR into memory is simple, but a streaming reader would be
+    // ideal for large repos.
+    let mut buf = Vec::new();
+    r.read_to_end(&mut buf)?;
+    let (header, mut offset) = read_section(&buf, 0)?.ok_or_else(|| CarError::Header {
+        message: String::from("missing header"),
+    })?;
+    let roots = parse_header(header)?;
+    while let Some((section, next)) = read_section(&buf, offset)? {
+        offset = next;
+        let (_, cid, len) = Cid::read_cid_v1(section)?;
+        let data = &section[len..];
+        if <Cid as ContentId>::hash(data) != cid {
+            return Err(CarError::HashMismatch { cid });
+        }
+        store.write_unchecked(&cid, data.to_vec()).await?;
+    }
+    Ok(roots)
+}
+/// Find the Cids linked to by the given block.
+//
+// TODO: Move this to `ContainedCids` once containers report their children. For now we scan
+// for the multihash prefix and only keep Cids that exist in the store, which can include
+// false positives but will never miss a link.
+async fn linked_cids<S: ContentStore>(store: &S, bytes: &[u8]) -> Result<Vec<Cid>, CarError> {
+    let cid_len = <Cid as ContentId>::hash(&[]).size();
+    let mut cids = Vec::new();
+    let mut i = 0;
+    while i + cid_len <= bytes.len() {
+        if bytes[i..].starts_with(&MULTIHASH_PREFIX) {
+            let cid = <Cid as ContentId>::from_hash(bytes[i..i + cid_len].to_vec())?;
+            if store.exists(&cid).await? {
+                cids.push(cid);
+                i += cid_len;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    Ok(cids)
+}
+fn write_section<W: Write>(w: &mut W, parts: &[&[u8]]) -> Result<(), CarError> {
+    let len = parts.iter().map(|part| part.len()).sum::<usize>();
+    let mut prefix = Vec::new();
+    varint::encode(len as u64, &mut prefix);
+    w.write_all(&prefix)?;
+    for part in parts {
+        w.write_all(part)?;
+    }
+    Ok(())
+}
+/// Read a varint length prefixed section starting at `offset`, returning the section and the
+/// offset after it, or `None` if `buf` is exhausted.
+fn read_section(buf: &[u8], offset: usize) -> Result<Option<(&[u8], usize)>, CarError> {
+    if offset == buf.len() {
+        return Ok(None);
+    }
+    let (len, varint_len) = varint::decode(&buf[offset..])?;
+    let start = offset + varint_len;
+    let end = usize::try_from(len)
+        .ok()
+        .and_then(|len| start.checked_add(len))
+        .ok_or(CarError::SectionLength)?;
+    let section = buf.get(start..end).ok_or(CarError::SectionLength)?;
+    Ok(Some((section, end)))
+}
+fn cbor_head(major: u8, arg: u64, buf: &mut Vec<u8>) {
+    let major = major << 5;
+    match arg {
+        0..=23 => buf.push(major | arg as u8),
+        24..=0xff => buf.extend_from_slice(&[major | 24, arg as u8]),
+        0x100..=0xffff => {
+            buf.push(major | 25);
+            buf.extend_from_slice(&(arg as u16).to_be_bytes());
+        },
+        0x1_0000..=0xffff_ffff => {
+            buf.push(major | 26);
+            buf.extend_from_slice(&(arg as u32).to_be_bytes());
+        },
+        _ => {
+            buf.push(major | 27);
+            buf.extend_from_slice(&arg.to_be_bytes());
+        },
+    }
+}
+fn cbor_text(s: &str, buf: &mut Vec<u8>) {
+    cbor_head(CBOR_MAJOR_TEXT, s.len() as u64, buf);
+    buf.extend_from_slice(s.as_bytes());
+}
+/// A minimal reader of the CBOR subset used by CARv1 headers.
+struct CborReader<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+impl<'a> CborReader<'a> {
+    fn header_err(message: &str) -> CarError {
+        CarError::Header {
+            message: message.to_string(),
+        }
+    }
+    fn take(&mut self, len: u64) -> Result<&'a [u8], CarError> {
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| self.offset.checked_add(len))
+            .ok_or_else(|| Self::header_err("length exceeds header"))?;
+        let bytes = self
+            .buf
+            .get(self.offset..end)
+            .ok_or_else(|| Self::header_err("unexpected end of header"))?;
+        self.offset = end;
+        Ok(bytes)
+    }
+    fn head(&mut self) -> Result<(u8, u64), CarError> {
+        let b = self.take(1)?[0];
+        let (major, info) = (b >> 5, b & 0x1f);
+        let arg = match info {
+            0..=23 => u64::from(info),
+            24 => u64::from(self.take(1)?[0]),
+            25 => u64::from(u16::from_be_bytes(self.take(2)?.try_into().unwrap())),
+            26 => u64::from(u32::from_be_bytes(self.take(4)?.try_into().unwrap())),
+            27 => u64::from_be_bytes(self.take(8)?.try_into().unwrap()),
+            _ => return Err(Self::header_err("unsupported cbor encoding")),
+        };
+        Ok((major, arg))
+    }
+    fn expect(&mut self, major: u8) -> Result<u64, CarError> {
+        match self.head()? {
+            (m, arg) if m == major => Ok(arg),
+            _ => Err(Self::header_err("unexpected cbor type")),
+        }
+    }
+    fn text(&mut self) -> Result<&'a [u8], CarError> {
+        let len = self.expect(CBOR_MAJOR_TEXT)?;
+        self.take(len)
+    }
+}
+fn parse_header(header: &[u8]) -> Result<Vec<Cid>, CarError> {
+    let mut r = CborReader {
+        buf: header,
+        offset: 0,
+    };
+    let mut roots = None;
+    let mut version = None;
+    for _ in 0..r.expect(CBOR_MAJOR_MAP)? {
+        match r.text()? {
+            b"roots" => {
+                let len = r.expect(CBOR_MAJOR_ARRAY)?;
+                let mut cids = Vec::new();
+                for _ in 0..len {
+                    if r.expect(CBOR_MAJOR_TAG)? != CBOR_TAG_CID {
+                        return Err(CborReader::header_err("expected cid tag"));
+                    }
+                    let len = r.expect(CBOR_MAJOR_BYTES)?;
+                    match r.take(len)? {
+                        [0, cid @ ..] => cids.push(Cid::from_cid_v1(cid)?.1),
+                        _ => return Err(CborReader::header_err("invalid cid link")),
+                    }
+                }
+                roots = Some(cids);
+            },
+            b"version" => version = Some(r.expect(CBOR_MAJOR_UINT)?),
+            _ => return Err(CborReader::header_err("unexpected header field")),
+        }
+    }
+    if version != Some(CAR_VERSION) {
+        return Err(CborReader::header_err("unsupported version"));
+    }
+    roots.ok_or_else(|| CborReader::header_err("missing roots"))
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::stores::memory::Memory;
+    #[tokio::test]
+    async fn roundtrip() {
+        let a = Memory::test();
+        let leaf = b"leaf".to_vec();
+        let leaf_cid = <Cid as ContentId>::hash(&leaf);
+        a.write_unchecked(&leaf_cid, leaf.clone()).await.unwrap();
+        let mut root = b"root:".to_vec();
+        root.extend_from_slice(leaf_cid.as_ref());
+        let root_cid = <Cid as ContentId>::hash(&root);
+        a.write_unchecked(&root_cid, root.clone()).await.unwrap();
+        let unrelated_cid = <Cid as ContentId>::hash(b"unrelated");
+        a.write_unchecked(&unrelated_cid, b"unrelated".to_vec())
+            .await
+            .unwrap();
+        let mut car = Vec::new();
+        export(&a, &[root_cid], &mut car).await.unwrap();
+        let b = Memory::test();
+        let roots = import(&b, car.as_slice()).await.unwrap();
+        assert_eq!(roots, vec![root_cid]);
+        assert_eq!(b.read_unchecked(&root_cid).await.unwrap().as_ref(), root);
+        assert_eq!(b.read_unchecked(&leaf_cid).await.unwrap().as_ref(), leaf);
+        assert!(!b.exists(&unrelated_cid).await.unwrap());
+    }
+    #[tokio::test]
+    async fn import_rejects_mismatched_block() {
+        let a = Memory::test();
+        let cid = <Cid as ContentId>::hash(b"foo");
+        a.write_unchecked(&cid, b"bar".to_vec()).await.unwrap();
+        let mut car = Vec::new();
+        export(&a, &[cid], &mut car).await.unwrap();
+        let b = Memory::test();
+        assert!(matches!(
+            import(&b, car.as_slice()).await,
+            Err(CarError::HashMismatch { .. })
+        ));
+    }
+    #[tokio::test]
+    async fn import_rejects_huge_lengths() {
+        let store = Memory::test();
+        let mut car = Vec::new();
+        varint::encode(u64::MAX, &mut car);
+        car.extend_from_slice(b"header");
+        assert!(matches!(
+            import(&store, car.as_slice()).await,
+            Err(CarError::SectionLength)
+        ));
+        // A header claiming a text field longer than the header itself.
+        let mut header = Vec::new();
+        cbor_head(CBOR_MAJOR_MAP, 1, &mut header);
+        cbor_head(CBOR_MAJOR_TEXT, u64::MAX, &mut header);
+        let mut car = Vec::new();
+        write_section(&mut car, &[&header]).unwrap();
+        assert!(matches!(
+            import(&store, car.as_slice()).await,
+            Err(CarError::Header { .. })
+        ));
+    }
+}
@@ -52,7 +52,7 @@ where
         store: &Arc<S>,
         cids_buf: &mut Vec<Cid>,
     ) -> Result<(), StoreError> {
-        self.save_with_cids(store, cids_buf).await
+        store.put_with_cids(self, cids_buf).await
     }
 }
 /// Like [`Default`], but with a store reference to keep as needed.
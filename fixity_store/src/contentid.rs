@@ -34,6 +34,16 @@ pub trait ContentId:
 pub enum FromHashError {
     #[error("invalid length")]
     Length,
+    #[error("invalid varint")]
+    Varint,
+    #[error("unsupported cid version: {0}")]
+    Version(u64),
+    #[error("unsupported codec: {0:#x}")]
+    Codec(u64),
+    #[error("unsupported multihash code: {0:#x}")]
+    Multihash(u64),
+    #[error("invalid encoding: {message}")]
+    Encoding { message: String },
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -112,6 +122,124 @@ impl PartialEq<CidArray> for Cid {
         &self.0 == other
     }
 }
+/// The version prefix of a [CIDv1](https://github.com/multiformats/cid#cidv1).
+const CID_V1: u64 = 1;
+/// The multihash code of the hash used by [`Cid`], `blake2b-256`.
+const BLAKE2B_256: u64 = 0xb220;
+/// The digest length of the hash used by [`Cid`].
+const BLAKE2B_256_LEN: u64 = 32;
+/// The leading bytes of every multihash produced by [`Cid`], aka the varint encoded
+/// `blake2b-256` code and digest length.
+pub(crate) const MULTIHASH_PREFIX: [u8; 4] = [0xa0, 0xe4, 0x02, 0x20];
+/// The multicodec of a block, used when converting a [`Cid`] to and from a CIDv1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Codec {
+    /// A fixity block serialized with rkyv.
+    ///
+    /// rkyv has no registered multicodec, so this uses a code from the multicodec private use
+    /// range. Other IPFS implementations can store and transfer these blocks, but not decode them.
+    FixityRkyv,
+}
+impl Codec {
+    const FIXITY_RKYV: u64 = 0x30_0000;
+    /// The multicodec code of this codec.
+    pub fn code(&self) -> u64 {
+        match self {
+            Self::FixityRkyv => Self::FIXITY_RKYV,
+        }
+    }
+    pub fn from_code(code: u64) -> Result<Self, FromHashError> {
+        match code {
+            Self::FIXITY_RKYV => Ok(Self::FixityRkyv),
+            code => Err(FromHashError::Codec(code)),
+        }
+    }
+}
+impl Cid {
+    /// Convert this `Cid` to the binary form of a CIDv1 with the given codec.
+    ///
+    /// The inner multihash is kept as is, so the conversion is lossless and the resulting CIDv1
+    /// addresses the same bytes as this `Cid`.
+    pub fn to_cid_v1(&self, codec: Codec) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(CID_LENGTH + 4);
+        varint::encode(CID_V1, &mut buf);
+        varint::encode(codec.code(), &mut buf);
+        buf.extend_from_slice(&self.0);
+        buf
+    }
+    /// Construct a `Cid` from the binary form of a CIDv1, returning the codec of the CIDv1.
+    pub fn from_cid_v1(buf: &[u8]) -> Result<(Codec, Self), FromHashError> {
+        let (codec, cid, len) = Self::read_cid_v1(buf)?;
+        if len != buf.len() {
+            return Err(FromHashError::Length);
+        }
+        Ok((codec, cid))
+    }
+    /// Like [`Self::from_cid_v1`], but allowing for trailing bytes after the CIDv1 and returning
+    /// the number of bytes read.
+    pub(crate) fn read_cid_v1(buf: &[u8]) -> Result<(Codec, Self, usize), FromHashError> {
+        let (version, mut offset) = varint::decode(buf)?;
+        if version != CID_V1 {
+            return Err(FromHashError::Version(version));
+        }
+        let (codec, len) = varint::decode(&buf[offset..])?;
+        let codec = Codec::from_code(codec)?;
+        offset += len;
+        let multihash_start = offset;
+        let (code, len) = varint::decode(&buf[offset..])?;
+        if code != BLAKE2B_256 {
+            return Err(FromHashError::Multihash(code));
+        }
+        offset += len;
+        let (digest_len, len) = varint::decode(&buf[offset..])?;
+        if digest_len != BLAKE2B_256_LEN {
+            return Err(FromHashError::Length);
+        }
+        offset += len + digest_len as usize;
+        let multihash = buf
+            .get(multihash_start..offset)
+            .ok_or(FromHashError::Length)?;
+        let cid = <Self as ContentId>::from_hash(multihash.to_vec())?;
+        Ok((codec, cid, offset))
+    }
+    /// Encode this `Cid` as a CIDv1 string, using the base32 multibase preferred by IPFS.
+    pub fn encode_cid_v1(&self, codec: Codec) -> String {
+        multibase::encode(Base::Base32Lower, self.to_cid_v1(codec))
+    }
+    /// Construct a `Cid` from an encoded CIDv1 string of any multibase.
+    pub fn decode_cid_v1(encoded: &str) -> Result<(Codec, Self), FromHashError> {
+        let (_, buf) = multibase::decode(encoded).map_err(|err| FromHashError::Encoding {
+            message: err.to_string(),
+        })?;
+        Self::from_cid_v1(&buf)
+    }
+}
+/// Unsigned varint encoding, as used by the multiformats.
+pub(crate) mod varint {
+    use super::FromHashError;
+
+    /// The max length of a `u64` varint.
+    const MAX_LEN: usize = 10;
+
+    pub fn encode(mut value: u64, buf: &mut Vec<u8>) {
+        while value >= 0x80 {
+            buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        buf.push(value as u8);
+    }
+    /// Decode a varint from the start of `buf`, returning the value and the number of bytes read.
+    pub fn decode(buf: &[u8]) -> Result<(u64, usize), FromHashError> {
+        let mut value = 0u64;
+        for (i, &b) in buf.iter().take(MAX_LEN).enumerate() {
+            value |= u64::from(b & 0x7f) << (i * 7);
+            if b & 0x80 == 0 {
+                return Ok((value, i + 1));
+            }
+        }
+        Err(FromHashError::Varint)
+    }
+}
 pub trait ContainedCids<Cid: ContentId> {
     fn contained_cids<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Cid> + Send + 'a>;
 }
@@ -152,4 +280,47 @@ pub mod test {
             Self(buf)
         }
     }
+    #[cfg(test)]
+    mod cid_v1 {
+        use super::super::*;
+        #[test]
+        fn roundtrip() {
+            let cid = <Cid as ContentId>::hash(b"hello world");
+            let codec = Codec::FixityRkyv;
+            assert_eq!(
+                Cid::from_cid_v1(&cid.to_cid_v1(codec)).unwrap(),
+                (codec, cid)
+            );
+            assert_eq!(
+                Cid::decode_cid_v1(&cid.encode_cid_v1(codec)).unwrap(),
+                (codec, cid)
+            );
+        }
+        #[test]
+        fn ipfs_compatible() {
+            // The CIDv1 of a raw, blake2b-256 hashed `hello world` block, aka the version and
+            // `raw` codec followed by the same multihash.
+            let cid = <Cid as ContentId>::hash(b"hello world");
+            let (_, ipfs) =
+                multibase::decode("bafk2bzaceaswza5ss4iu2ia3galz6pyo6dfm5f4dmiw2lf2de22dmf4k533ba")
+                    .unwrap();
+            assert_eq!(ipfs[..2], [0x01, 0x55]);
+            assert_eq!(ipfs[2..], cid.to_cid_v1(Codec::FixityRkyv)[5..]);
+        }
+        #[test]
+        fn rejects_invalid() {
+            let cid = <Cid as ContentId>::hash(b"hello world");
+            let mut buf = cid.to_cid_v1(Codec::FixityRkyv);
+            buf[0] = 2;
+            assert!(matches!(
+                Cid::from_cid_v1(&buf),
+                Err(FromHashError::Version(2))
+            ));
+            let buf = cid.to_cid_v1(Codec::FixityRkyv);
+            assert!(matches!(
+                Cid::from_cid_v1(&buf[..buf.len() - 1]),
+                Err(FromHashError::Length)
+            ));
+        }
+    }
 }
@@ -1,10 +1,12 @@
 // A hopefully short term unstable feature, stype Container = ype Container = nce GATs are
 // stablizing soon.
+pub mod car;
 pub mod container;
 pub mod contentid;
 pub mod deser;
 pub mod deser_ext;
 pub mod replicaid;
+pub mod replicakey;
 pub mod storage;
 pub mod store;
 pub use storage::{ContentStorage, MutStorage};
@@ -0,0 +1,116 @@
+//! Ed25519 keys for replicas, where a [`Rid`] is the public key of the replica that owns it.
+use crate::{
+    contentid::Cid,
+    replicaid::{FromBufError, RandReplicaBuf, ReplicaId, Rid},
+};
+use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey, SECRET_KEY_LENGTH};
+use std::fmt::Debug;
+use thiserror::Error;
+
+pub const SIGNATURE_LENGTH: usize = ed25519_dalek::SIGNATURE_LENGTH;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    #[error("replica id is not a valid public key")]
+    InvalidKey,
+    #[error("signature does not match")]
+    InvalidSignature,
+}
+/// The private signing key of a replica.
+///
+/// The [`Rid`] of the replica is the public key of this keypair, allowing any reader to verify
+/// content signed by a replica with nothing but the `Rid`.
+#[derive(Clone)]
+pub struct ReplicaKey(SigningKey);
+impl ReplicaKey {
+    /// Generate a new key from the given source of randomness.
+    pub fn new<R: RandReplicaBuf>(rand: &mut R) -> Result<Self, FromBufError> {
+        let seed = <[u8; SECRET_KEY_LENGTH]>::try_from(rand.new(SECRET_KEY_LENGTH))
+            .map_err(|_| FromBufError::Length)?;
+        Ok(Self::from_seed(seed))
+    }
+    /// Construct the key from a secret seed, such as one previously returned by
+    /// [`Self::to_seed`].
+    pub fn from_seed(seed: [u8; SECRET_KEY_LENGTH]) -> Self {
+        Self(SigningKey::from_bytes(&seed))
+    }
+    pub fn to_seed(&self) -> [u8; SECRET_KEY_LENGTH] {
+        self.0.to_bytes()
+    }
+    /// The replica id of this key, aka the public key.
+    pub fn rid(&self) -> Rid {
+        Rid::from(self.0.verifying_key().to_bytes())
+    }
+    /// Sign the given content id.
+    pub fn sign(&self, cid: &Cid) -> ReplicaSig {
+        ReplicaSig(self.0.sign(cid.as_ref()).to_bytes())
+    }
+}
+impl Debug for ReplicaKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the secret half.
+        write!(f, "ReplicaKey({})", self.rid())
+    }
+}
+/// A signature by a replica over a content id.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Deserialize, rkyv::Serialize, rkyv::Archive)
+)]
+pub struct ReplicaSig([u8; SIGNATURE_LENGTH]);
+impl Debug for ReplicaSig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ReplicaSig({})",
+            multibase::encode(multibase::Base::Base58Btc, self.0)
+        )
+    }
+}
+impl From<[u8; SIGNATURE_LENGTH]> for ReplicaSig {
+    fn from(arr: [u8; SIGNATURE_LENGTH]) -> Self {
+        Self(arr)
+    }
+}
+impl AsRef<[u8]> for ReplicaSig {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+impl Rid {
+    /// Verify that the given signature over `cid` was made by the key of this replica.
+    pub fn verify(&self, cid: &Cid, sig: &ReplicaSig) -> Result<(), SignatureError> {
+        let key =
+            VerifyingKey::from_bytes(self.as_buf()).map_err(|_| SignatureError::InvalidKey)?;
+        let sig = ed25519_dalek::Signature::from_bytes(&sig.0);
+        key.verify(cid.as_ref(), &sig)
+            .map_err(|_| SignatureError::InvalidSignature)
+    }
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::contentid::ContentId;
+    #[test]
+    fn sign_verify() {
+        let key = ReplicaKey::from_seed([1; SECRET_KEY_LENGTH]);
+        let other = ReplicaKey::from_seed([2; SECRET_KEY_LENGTH]);
+        let cid = <Cid as ContentId>::hash(b"foo");
+        let sig = key.sign(&cid);
+        assert_eq!(key.rid().verify(&cid, &sig), Ok(()));
+        assert_eq!(
+            other.rid().verify(&cid, &sig),
+            Err(SignatureError::InvalidSignature)
+        );
+        assert_eq!(
+            key.rid().verify(&<Cid as ContentId>::hash(b"bar"), &sig),
+            Err(SignatureError::InvalidSignature)
+        );
+        assert_eq!(
+            ReplicaKey::from_seed(key.to_seed()).rid(),
+            key.rid(),
+            "rid is derived from the seed"
+        );
+    }
+}
@@ -1,7 +1,10 @@
 // pub mod json_store;
 // pub mod rkyv_store;
 
-use crate::storage::StorageError;
+use crate::{
+    content_store::ContentStoreError, deser::DeserError, replicaid::Rid,
+    replicakey::SignatureError, storage::StorageError,
+};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -16,8 +19,24 @@ pub enum StoreError {
     // TODO: move to diff error type.
     #[error("type cannot be diff'd")]
     UndiffableType,
+    #[error("keys are not in ascending order")]
+    UnsortedKeys,
+    #[error("a replica key is required to sign")]
+    MissingReplicaKey,
+    #[error("a chunking key is required for a keyed blob")]
+    MissingChunkingKey,
+    #[error("invalid signature: {0}")]
+    Signature(#[from] SignatureError),
+    #[error("expected replica {expected}, got {got}")]
+    UnexpectedReplica { expected: Rid, got: Rid },
     #[error("storage: {0}")]
     Storage(StorageError),
+    #[error("content store: {0}")]
+    ContentStore(ContentStoreError),
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("deser: {0}")]
+    Deser(#[from] DeserError),
 }
 impl From<StorageError> for StoreError {
     fn from(err: StorageError) -> Self {
@@ -27,3 +46,11 @@ impl From<StorageError> for StoreError {
         }
     }
 }
+impl From<ContentStoreError> for StoreError {
+    fn from(err: ContentStoreError) -> Self {
+        match err {
+            ContentStoreError::NotFound => Self::NotFound,
+            err => Self::ContentStore(err),
+        }
+    }
+}
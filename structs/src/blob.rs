@@ -0,0 +1,388 @@
+use crate::prolly_list::ProllyList;
+use async_trait::async_trait;
+use fbuzhash::chunker::{BuzHashChunker, Chunker};
+use fixity_store::{
+    container::{ContainerDescription, DescribeContainer, PersistContainer, ReconcileContainer},
+    content_store::ContentStore,
+    contentid::Cid,
+    deser_ext::DeserExt,
+    store::StoreError,
+};
+use std::{mem, sync::Arc};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const DEFAULT_WINDOW_SIZE: u32 = 64;
+const DEFAULT_MIN: usize = 1024 * 16;
+const DEFAULT_PATTERN: u32 = (1 << 15) - 1;
+const DEFAULT_MAX: usize = 1024 * 64;
+/// The size of reads from the source of a [`Blob::write`].
+const READ_BUF_SIZE: usize = 1024 * 8;
+
+/// Chunking parameters of a [`Blob`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub window_size: u32,
+    /// The smallest chunk, aside from the last chunk of a blob.
+    pub min: usize,
+    /// The pattern ending a chunk, see [`BuzHashChunker::new`].
+    pub pattern: u32,
+    /// The largest chunk, ended regardless of the pattern.
+    pub max: usize,
+    /// A secret key to derive chunk boundaries from, such that chunk sizes reveal nothing about
+    /// the content to those without the key.
+    ///
+    /// Blobs only dedupe against blobs chunked with the same key.
+    pub key: Option<[u8; 32]>,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            window_size: DEFAULT_WINDOW_SIZE,
+            min: DEFAULT_MIN,
+            pattern: DEFAULT_PATTERN,
+            max: DEFAULT_MAX,
+            key: None,
+        }
+    }
+}
+/// The persisted form of a [`Blob`].
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)
+)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Root {
+    /// The [`ProllyList`] of chunk Cids.
+    pub chunks: Cid,
+    /// The size of the blob in bytes.
+    pub len: u64,
+    /// The chunking parameters of the blob, see [`Config`], such that later writes chunk alike.
+    pub window_size: u32,
+    pub min: u64,
+    pub pattern: u32,
+    pub max: u64,
+    /// Whether the blob was chunked with a key, which is never stored.
+    pub keyed: bool,
+}
+/// A large binary value, split into content-defined chunks such that an edit to the value only
+/// writes the chunks around the edit.
+#[derive(Debug)]
+pub struct Blob {
+    config: Config,
+    chunks: ProllyList<Cid>,
+    len: u64,
+    /// Chunks written since the last save.
+    written: Vec<Cid>,
+}
+impl Blob {
+    pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            config,
+            chunks: ProllyList::new(),
+            len: 0,
+            written: Vec::new(),
+        }
+    }
+    /// The size of the blob in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Open a blob chunked with the given [key](Config::key), which [`PersistContainer::open`]
+    /// refuses to open.
+    ///
+    /// The key is not checked against the blob, a different key only loses dedupe against the
+    /// existing chunks.
+    pub async fn open_with_key<S>(
+        store: &Arc<S>,
+        cid: &Cid,
+        key: [u8; 32],
+    ) -> Result<Self, StoreError>
+    where
+        S: ContentStore,
+    {
+        let root = store.get_owned_unchecked::<Root>(cid).await?;
+        Self::from_root(store, root, Some(key)).await
+    }
+    async fn from_root<S>(
+        store: &Arc<S>,
+        root: Root,
+        key: Option<[u8; 32]>,
+    ) -> Result<Self, StoreError>
+    where
+        S: ContentStore,
+    {
+        let config = Config {
+            window_size: root.window_size,
+            min: root.min as usize,
+            pattern: root.pattern,
+            max: root.max as usize,
+            key,
+        };
+        Ok(Self {
+            chunks: ProllyList::open(store, &root.chunks).await?,
+            len: root.len,
+            ..Self::with_config(config)
+        })
+    }
+    /// Replace the content of the blob with everything read from `r`, returning the number of
+    /// bytes read.
+    pub async fn write<S, R>(&mut self, store: &Arc<S>, mut r: R) -> Result<u64, StoreError>
+    where
+        S: ContentStore,
+        R: AsyncRead + Unpin + Send,
+    {
+        let Config {
+            window_size,
+            min,
+            pattern,
+            max,
+            key,
+        } = self.config;
+        let mut chunks = ProllyList::new();
+        // Chunks of an unsaved earlier write are only reported if written again.
+        let mut written = Vec::new();
+        let mut chunker = BuzHashChunker::new(window_size, pattern, min, max);
+        if let Some(key) = key.as_ref() {
+            chunker = chunker.with_key(key);
+        }
+        let mut chunk = Vec::with_capacity(max);
+        let mut buf = vec![0; READ_BUF_SIZE];
+        let mut len = 0;
+        loop {
+            let n = r.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            len += n as u64;
+            let mut read = &buf[..n];
+            while let Some(boundary) = chunker.next_boundary(read) {
+                chunk.extend_from_slice(&read[..boundary]);
+                chunks.push(write_chunk(store, mem::take(&mut chunk), &mut written).await?);
+                read = &read[boundary..];
+            }
+            chunk.extend_from_slice(read);
+        }
+        if !chunk.is_empty() {
+            chunks.push(write_chunk(store, chunk, &mut written).await?);
+        }
+        self.chunks = chunks;
+        self.len = len;
+        self.written = written;
+        Ok(len)
+    }
+    /// Write the content of the blob to `w`, returning the number of bytes written.
+    pub async fn read<S, W>(&self, store: &Arc<S>, mut w: W) -> Result<u64, StoreError>
+    where
+        S: ContentStore,
+        W: AsyncWrite + Unpin + Send,
+    {
+        let mut len = 0;
+        // NIT: The chunk index is read whole, which is small relative to the chunks themselves.
+        for cid in self.chunks.to_vec(store).await? {
+            let chunk = store.get_owned_unchecked::<Vec<u8>>(&cid).await?;
+            w.write_all(&chunk).await?;
+            len += chunk.len() as u64;
+        }
+        w.flush().await?;
+        Ok(len)
+    }
+}
+async fn write_chunk<S>(
+    store: &Arc<S>,
+    chunk: Vec<u8>,
+    written: &mut Vec<Cid>,
+) -> Result<Cid, StoreError>
+where
+    S: ContentStore,
+{
+    let cid = store.put(&chunk).await?;
+    written.push(cid);
+    Ok(cid)
+}
+impl Default for Blob {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl DescribeContainer for Blob {
+    fn description() -> ContainerDescription {
+        ContainerDescription {
+            name: "Blob",
+            params: Default::default(),
+        }
+    }
+}
+#[async_trait]
+impl<S> PersistContainer<S> for Blob
+where
+    S: ContentStore,
+{
+    /// Open a blob chunked without a key, see [`Blob::open_with_key`] otherwise.
+    async fn open(store: &Arc<S>, cid: &Cid) -> Result<Self, StoreError> {
+        let root = store.get_owned_unchecked::<Root>(cid).await?;
+        if root.keyed {
+            return Err(StoreError::MissingChunkingKey);
+        }
+        Self::from_root(store, root, None).await
+    }
+    async fn save(&mut self, store: &Arc<S>) -> Result<Cid, StoreError> {
+        let mut cids = Vec::new();
+        self.save_with_cids(store, &mut cids).await?;
+        Ok(cids.pop().expect("root cid written"))
+    }
+    async fn save_with_cids(
+        &mut self,
+        store: &Arc<S>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<(), StoreError> {
+        cids_buf.append(&mut self.written);
+        self.chunks.save_with_cids(store, cids_buf).await?;
+        let Config {
+            window_size,
+            min,
+            pattern,
+            max,
+            key,
+        } = self.config;
+        let root = Root {
+            chunks: *cids_buf.last().expect("chunks cid written"),
+            len: self.len,
+            window_size,
+            min: min as u64,
+            pattern,
+            max: max as u64,
+            keyed: key.is_some(),
+        };
+        store.put_with_cids(&root, cids_buf).await
+    }
+}
+#[async_trait]
+impl<S> ReconcileContainer<S> for Blob
+where
+    S: ContentStore,
+{
+    async fn merge(&mut self, _: &Arc<S>, _: &Cid) -> Result<(), StoreError> {
+        Err(StoreError::UnmergableType)
+    }
+    async fn diff(&mut self, _: &Arc<S>, _: &Cid) -> Result<Self, StoreError> {
+        Err(StoreError::UndiffableType)
+    }
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use fbuzhash::test_util::bytes;
+    use fixity_store::stores::memory::Memory;
+
+    fn test_config() -> Config {
+        Config {
+            window_size: DEFAULT_WINDOW_SIZE,
+            min: 256,
+            pattern: (1 << 10) - 1,
+            max: 4096,
+            key: None,
+        }
+    }
+    #[tokio::test]
+    async fn write_read() {
+        let store = Memory::test();
+        let keyed = Config {
+            key: Some([1; 32]),
+            ..test_config()
+        };
+        for (content, config) in [
+            (Vec::new(), test_config()),
+            (vec![7], test_config()),
+            (bytes(100_000, 1), test_config()),
+            (bytes(100_000, 1), keyed),
+        ] {
+            let mut blob = Blob::with_config(config);
+            let len = blob.write(&store, content.as_slice()).await.unwrap();
+            assert_eq!(len, content.len() as u64);
+            let cid = blob.save(&store).await.unwrap();
+            let blob = match config.key {
+                Some(key) => Blob::open_with_key(&store, &cid, key).await.unwrap(),
+                None => Blob::open(&store, &cid).await.unwrap(),
+            };
+            assert_eq!(blob.len(), content.len() as u64);
+            let mut read = Vec::new();
+            assert_eq!(blob.read(&store, &mut read).await.unwrap(), len);
+            assert_eq!(read, content);
+        }
+    }
+    #[tokio::test]
+    async fn edits_dedupe() {
+        let store = Memory::test();
+        let content = bytes(200_000, 2);
+        let mut blob = Blob::with_config(test_config());
+        blob.write(&store, content.as_slice()).await.unwrap();
+        let mut cids = Vec::new();
+        blob.save_with_cids(&store, &mut cids).await.unwrap();
+        let created = cids.len();
+        let mut edited = content.clone();
+        edited.splice(100_000..100_010, bytes(50, 3));
+        blob.write(&store, edited.as_slice()).await.unwrap();
+        let mut cids = Vec::new();
+        blob.save_with_cids(&store, &mut cids).await.unwrap();
+        let mut read = Vec::new();
+        blob.read(&store, &mut read).await.unwrap();
+        assert_eq!(read, edited);
+        // Only chunks around the edit, and the index nodes pointing to them, are new.
+        let new = {
+            let before = Memory::test();
+            let mut blob = Blob::with_config(test_config());
+            blob.write(&before, content.as_slice()).await.unwrap();
+            let mut old = Vec::new();
+            blob.save_with_cids(&before, &mut old).await.unwrap();
+            cids.iter().filter(|cid| !old.contains(cid)).count()
+        };
+        assert!(new < created / 10, "{} of {} blocks new", new, created);
+    }
+    #[tokio::test]
+    async fn reopened_edits_dedupe() {
+        let store = Memory::test();
+        let content = bytes(200_000, 4);
+        let mut edited = content.clone();
+        edited.splice(100_000..100_010, bytes(50, 5));
+        let keyed = Config {
+            key: Some([1; 32]),
+            ..test_config()
+        };
+        for config in [test_config(), keyed] {
+            let mut blob = Blob::with_config(config);
+            blob.write(&store, content.as_slice()).await.unwrap();
+            let mut created = Vec::new();
+            blob.save_with_cids(&store, &mut created).await.unwrap();
+            let cid = *created.last().unwrap();
+            let mut blob = match config.key {
+                Some(key) => {
+                    assert!(matches!(
+                        Blob::open(&store, &cid).await,
+                        Err(StoreError::MissingChunkingKey)
+                    ));
+                    Blob::open_with_key(&store, &cid, key).await.unwrap()
+                },
+                None => Blob::open(&store, &cid).await.unwrap(),
+            };
+            blob.write(&store, edited.as_slice()).await.unwrap();
+            let mut cids = Vec::new();
+            blob.save_with_cids(&store, &mut cids).await.unwrap();
+            let new = cids.iter().filter(|cid| !created.contains(cid)).count();
+            assert!(
+                new < created.len() / 10,
+                "{} of {} blocks new",
+                new,
+                created.len()
+            );
+            let mut read = Vec::new();
+            blob.read(&store, &mut read).await.unwrap();
+            assert_eq!(read, edited);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Content-defined chunking of containers persisted as a flat list of chunks, where the chunk
+//! boundaries are defined by the elements such that an edit only changes the chunk it falls in.
+//
+// PERF: Every chunk is still serialized on save to find its Cid, even when unchanged. Tracking the
+// changed chunks would avoid it.
+use fixity_store::{
+    content_store::ContentStore,
+    contentid::{Cid, ContentId},
+    deser::Serialize,
+    store::StoreError,
+};
+use std::{collections::HashSet, sync::Arc};
+
+/// A mask of the low bits of an element hash, an element ends a chunk when every bit of the mask
+/// is set. Chunks average `CHUNK_PATTERN + 1` elements.
+pub(crate) const CHUNK_PATTERN: u32 = (1 << 6) - 1;
+
+/// Whether the element serialized as `bytes` ends a chunk.
+pub(crate) fn is_boundary(bytes: &[u8]) -> bool {
+    let cid = <Cid as ContentId>::hash(bytes);
+    let hash = cid.as_hash();
+    let tail = u32::from_be_bytes(hash[hash.len() - 4..].try_into().unwrap());
+    tail & CHUNK_PATTERN == CHUNK_PATTERN
+}
+/// The chunks of a container as of its last open or save, such that a save only writes the
+/// chunks changed since.
+///
+/// Always equal to any other, as it only caches what is already in the store.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StoredChunks(HashSet<Cid>);
+impl StoredChunks {
+    pub(crate) fn new(cids: &[Cid]) -> Self {
+        Self(cids.iter().copied().collect())
+    }
+    /// Write the chunks not already stored, returning the Cid of every chunk in order.
+    pub(crate) async fn save<S, C>(
+        &mut self,
+        store: &Arc<S>,
+        chunks: Vec<C>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<Vec<Cid>, StoreError>
+    where
+        S: ContentStore,
+        C: Serialize,
+    {
+        let mut cids = Vec::with_capacity(chunks.len());
+        for chunk in chunks {
+            let buf: Vec<u8> = chunk.serialize()?.into();
+            let cid = <Cid as ContentId>::hash(&buf);
+            if !self.0.contains(&cid) {
+                store.write_unchecked(&cid, buf).await?;
+                cids_buf.push(cid);
+            }
+            cids.push(cid);
+        }
+        *self = Self::new(&cids);
+        Ok(cids)
+    }
+}
+impl PartialEq for StoredChunks {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+impl Eq for StoredChunks {}
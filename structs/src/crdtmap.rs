@@ -0,0 +1,349 @@
+use crate::ptr::{Ptr, Resolver};
+use async_trait::async_trait;
+use fixity_store::{
+    container::{ContainerDescription, DescribeContainer, PersistContainer, ReconcileContainer},
+    content_store::ContentStore,
+    contentid::Cid,
+    deser::{Deserialize, Serialize},
+    deser_ext::DeserExt,
+    store::StoreError,
+};
+use std::{collections::BTreeMap, sync::Arc};
+
+/// The persisted form of a [`CrdtMap`], each key and the Cid of its value sorted by key.
+type Root<K> = Vec<(K, Cid)>;
+
+/// A map of keys to containers, where each value is merged key-by-key with the semantics of its
+/// own container, allowing counters, registers and maps to nest within a single repo.
+///
+/// Values are stored as their own blocks and loaded lazily, such that a save only rewrites the
+/// values changed since they were loaded or last saved.
+///
+/// Keys are never removed, as there is no removal that merges sensibly with concurrent changes
+/// to arbitrary values.
+#[derive(Debug, Clone)]
+pub struct CrdtMap<K, V> {
+    entries: BTreeMap<K, Ptr<Cid, V>>,
+    resolver: Resolver<Cid, V>,
+    /// Values written by the [`ReconcileContainer::diff`] that returned this map, reported by its
+    /// next save.
+    written: Vec<Cid>,
+}
+impl<K, V> CrdtMap<K, V>
+where
+    K: Ord,
+{
+    pub fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            resolver: Resolver::default(),
+            written: Vec::new(),
+        }
+    }
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.keys()
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// Insert the value, replacing any local value of the key.
+    ///
+    /// Values of the key on other replicas are still merged into this value, as with any other
+    /// change to the value.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.entries.insert(key, Ptr::new(value));
+    }
+    pub async fn get<S>(&mut self, store: &Arc<S>, key: &K) -> Result<Option<&V>, StoreError>
+    where
+        S: ContentStore,
+        V: PersistContainer<S>,
+    {
+        match self.entries.get_mut(key) {
+            Some(ptr) => Ok(Some(self.resolver.resolve(store, ptr).await?)),
+            None => Ok(None),
+        }
+    }
+    /// Get the value of the key to modify, which will be written on the next save.
+    pub async fn get_mut<S>(
+        &mut self,
+        store: &Arc<S>,
+        key: &K,
+    ) -> Result<Option<&mut V>, StoreError>
+    where
+        S: ContentStore,
+        V: PersistContainer<S> + Clone,
+    {
+        match self.entries.get_mut(key) {
+            Some(ptr) => Ok(Some(self.resolver.resolve_mut(store, ptr).await?)),
+            None => Ok(None),
+        }
+    }
+    /// Like [`Self::get_mut`], inserting a [default](fixity_store::container::DefaultContainer)
+    /// value if the key is missing.
+    pub async fn get_or_default<S>(&mut self, store: &Arc<S>, key: K) -> Result<&mut V, StoreError>
+    where
+        S: ContentStore,
+        V: PersistContainer<S> + Clone,
+    {
+        let ptr = self
+            .entries
+            .entry(key)
+            .or_insert_with(|| Ptr::new(V::default_container(store)));
+        self.resolver.resolve_mut(store, ptr).await
+    }
+}
+impl<K: Ord, V> Default for CrdtMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K, V> DescribeContainer for CrdtMap<K, V>
+where
+    V: DescribeContainer,
+{
+    fn description() -> ContainerDescription {
+        // TODO: Describe `K`, once params support non-container types.
+        ContainerDescription {
+            name: "CrdtMap",
+            params: vec![V::description()],
+        }
+    }
+}
+#[async_trait]
+impl<K, V, S> PersistContainer<S> for CrdtMap<K, V>
+where
+    S: ContentStore,
+    K: Ord + Clone + Send + Sync,
+    V: PersistContainer<S> + Sync,
+    Root<K>: Serialize + Deserialize,
+{
+    async fn open(store: &Arc<S>, cid: &Cid) -> Result<Self, StoreError> {
+        let root = store.get_owned_unchecked::<Root<K>>(cid).await?;
+        let entries = root
+            .into_iter()
+            .map(|(key, cid)| (key, Ptr::from_cid(cid)))
+            .collect();
+        Ok(Self {
+            entries,
+            ..Self::new()
+        })
+    }
+    async fn save(&mut self, store: &Arc<S>) -> Result<Cid, StoreError> {
+        let mut cids = Vec::new();
+        self.save_with_cids(store, &mut cids).await?;
+        Ok(cids.pop().expect("root cid written"))
+    }
+    async fn save_with_cids(
+        &mut self,
+        store: &Arc<S>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<(), StoreError> {
+        cids_buf.append(&mut self.written);
+        let mut root: Root<K> = Vec::with_capacity(self.entries.len());
+        for (key, ptr) in self.entries.iter_mut() {
+            let cid = ptr.save_with_cids(store, cids_buf).await?;
+            root.push((key.clone(), cid));
+        }
+        store.put_with_cids(&root, cids_buf).await
+    }
+}
+#[async_trait]
+impl<K, V, S> ReconcileContainer<S> for CrdtMap<K, V>
+where
+    S: ContentStore,
+    K: Ord + Clone + Send + Sync,
+    V: PersistContainer<S> + ReconcileContainer<S> + Clone + Sync,
+    Root<K>: Serialize + Deserialize,
+{
+    async fn merge(&mut self, store: &Arc<S>, other: &Cid) -> Result<(), StoreError> {
+        let other = store.get_owned_unchecked::<Root<K>>(other).await?;
+        for (key, other_cid) in other {
+            match self.entries.get_mut(&key) {
+                Some(ptr) if ptr.is_stored_as(&other_cid) => {},
+                Some(ptr) => {
+                    self.resolver
+                        .resolve_mut(store, ptr)
+                        .await?
+                        .merge(store, &other_cid)
+                        .await?
+                },
+                // The value is not loaded until it's needed.
+                None => {
+                    self.entries.insert(key, Ptr::from_cid(other_cid));
+                },
+            }
+        }
+        Ok(())
+    }
+    async fn diff(&mut self, store: &Arc<S>, other: &Cid) -> Result<Self, StoreError> {
+        let other = store
+            .get_owned_unchecked::<Root<K>>(other)
+            .await?
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        // Values missing from the other side are included whole, by Cid, so unsaved values are
+        // written. The returned diff reports them on its save.
+        let mut written = Vec::new();
+        let mut entries = BTreeMap::new();
+        for (key, ptr) in self.entries.iter_mut() {
+            let diff = match other.get(key) {
+                Some(other_cid) if ptr.is_stored_as(other_cid) => continue,
+                Some(other_cid) => {
+                    let diff = match ptr.cid().copied() {
+                        // `diff` takes `&mut self`, so an unchanged value is diffed from its own
+                        // copy rather than being marked as changed.
+                        Some(cid) => V::open(store, &cid).await?.diff(store, other_cid).await?,
+                        None => {
+                            self.resolver
+                                .resolve_mut(store, ptr)
+                                .await?
+                                .diff(store, other_cid)
+                                .await?
+                        },
+                    };
+                    Ptr::new(diff)
+                },
+                None => Ptr::from_cid(ptr.save_with_cids(store, &mut written).await?),
+            };
+            entries.insert(key.clone(), diff);
+        }
+        Ok(Self {
+            entries,
+            written,
+            ..Self::new()
+        })
+    }
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::{gcounter::GCounter, lwwregister::LwwRegister};
+    use fixity_store::stores::memory::Memory;
+
+    #[tokio::test]
+    async fn merge() {
+        let store = Memory::test();
+        let mut a = CrdtMap::<String, GCounter>::new();
+        a.get_or_default(&store, "x".into())
+            .await
+            .unwrap()
+            .inc(1.into());
+        let a_cid = a.save(&store).await.unwrap();
+        let mut b = CrdtMap::<String, GCounter>::open(&store, &a_cid)
+            .await
+            .unwrap();
+        b.get_mut(&store, &"x".into())
+            .await
+            .unwrap()
+            .unwrap()
+            .inc(2.into());
+        b.get_or_default(&store, "y".into())
+            .await
+            .unwrap()
+            .inc(2.into());
+        a.get_or_default(&store, "x".into())
+            .await
+            .unwrap()
+            .inc(1.into());
+        let a_cid = a.save(&store).await.unwrap();
+        let b_cid = b.save(&store).await.unwrap();
+        a.merge(&store, &b_cid).await.unwrap();
+        b.merge(&store, &a_cid).await.unwrap();
+        for map in [&mut a, &mut b] {
+            assert_eq!(map.keys().collect::<Vec<_>>(), vec!["x", "y"]);
+            let x = map.get(&store, &"x".into()).await.unwrap().unwrap();
+            assert_eq!(x.value(), 3);
+            let y = map.get(&store, &"y".into()).await.unwrap().unwrap();
+            assert_eq!(y.value(), 1);
+        }
+        assert_eq!(a.save(&store).await.unwrap(), b.save(&store).await.unwrap());
+    }
+    #[tokio::test]
+    async fn unchanged_values_not_rewritten() {
+        let store = Memory::test();
+        let mut a = CrdtMap::<u32, GCounter>::new();
+        for key in 0..10 {
+            a.get_or_default(&store, key).await.unwrap().inc(1.into());
+        }
+        let mut cids = Vec::new();
+        a.save_with_cids(&store, &mut cids).await.unwrap();
+        assert_eq!(cids.len(), 11, "every value and the root");
+        let mut b = CrdtMap::<u32, GCounter>::open(&store, cids.last().unwrap())
+            .await
+            .unwrap();
+        b.get_mut(&store, &5).await.unwrap().unwrap().inc(1.into());
+        let mut cids = Vec::new();
+        b.save_with_cids(&store, &mut cids).await.unwrap();
+        assert_eq!(cids.len(), 2, "only the changed value and the root");
+        // Reading a value does not change it.
+        assert!(b.get(&store, &6).await.unwrap().is_some());
+        let mut cids = Vec::new();
+        b.save_with_cids(&store, &mut cids).await.unwrap();
+        assert_eq!(cids.len(), 1);
+    }
+    #[tokio::test]
+    async fn nested() {
+        type Inner = CrdtMap<String, LwwRegister<String>>;
+        let store = Memory::test();
+        let mut a = CrdtMap::<String, Inner>::new();
+        let inner = a.get_or_default(&store, "user".into()).await.unwrap();
+        inner
+            .get_or_default(&store, "name".into())
+            .await
+            .unwrap()
+            .set_at(1.into(), "alice".into(), 1);
+        let a_cid = a.save(&store).await.unwrap();
+        let mut b = CrdtMap::<String, Inner>::open(&store, &a_cid)
+            .await
+            .unwrap();
+        let inner = b.get_mut(&store, &"user".into()).await.unwrap().unwrap();
+        inner
+            .get_or_default(&store, "email".into())
+            .await
+            .unwrap()
+            .set_at(2.into(), "b@example.com".into(), 2);
+        let inner = a.get_mut(&store, &"user".into()).await.unwrap().unwrap();
+        inner
+            .get_mut(&store, &"name".into())
+            .await
+            .unwrap()
+            .unwrap()
+            .set_at(1.into(), "alicia".into(), 3);
+        b.save(&store).await.unwrap();
+        let mut diff = b.diff(&store, &a_cid).await.unwrap();
+        let diff_cid = diff.save(&store).await.unwrap();
+        a.merge(&store, &diff_cid).await.unwrap();
+        let inner = a.get_mut(&store, &"user".into()).await.unwrap().unwrap();
+        let name = inner.get(&store, &"name".into()).await.unwrap().unwrap();
+        assert_eq!(name.get(), "alicia");
+        let email = inner.get(&store, &"email".into()).await.unwrap().unwrap();
+        assert_eq!(email.get(), "b@example.com");
+        b.merge(&store, &a.save(&store).await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(a.save(&store).await.unwrap(), b.save(&store).await.unwrap());
+        let b_cid = b.save(&store).await.unwrap();
+        assert!(b.diff(&store, &b_cid).await.unwrap().is_empty());
+    }
+    #[tokio::test]
+    async fn diff_reports_written_values() {
+        let store = Memory::test();
+        let mut a = CrdtMap::<u32, GCounter>::new();
+        let a_cid = a.save(&store).await.unwrap();
+        a.get_or_default(&store, 1).await.unwrap().inc(1.into());
+        let mut diff = a.diff(&store, &a_cid).await.unwrap();
+        let mut diff_cids = Vec::new();
+        diff.save_with_cids(&store, &mut diff_cids).await.unwrap();
+        assert_eq!(diff_cids.len(), 2, "the value and the root");
+        // The writes of a diff are only reported by the diff.
+        let mut cids = Vec::new();
+        a.save_with_cids(&store, &mut cids).await.unwrap();
+        assert_eq!(cids.len(), 1, "only the root");
+    }
+}
@@ -13,39 +13,116 @@ use fixity_store::{
     store::StoreError,
 };
 
-type GCounterInt = u32;
+pub type GCounterInt = u32;
+/// The persisted form of a [`GCounter`], sorted by `Rid`.
+pub(crate) type IVec = Vec<(Rid, GCounterInt)>;
 
-#[derive(Debug)]
-pub struct GCounter(Vec<(Rid, GCounterInt)>);
+#[derive(Debug, Clone)]
+pub struct GCounter {
+    counts: IVec,
+    /// Local increments since the last [`GCounter::take_delta`].
+    delta: IVec,
+}
 impl GCounter {
     pub fn new() -> Self {
-        Self(Default::default())
+        Self::from_ivec(Default::default())
+    }
+    pub(crate) fn from_ivec(counts: IVec) -> Self {
+        Self {
+            counts,
+            delta: Default::default(),
+        }
+    }
+    pub(crate) fn as_ivec(&self) -> &IVec {
+        &self.counts
     }
 }
 impl GCounter {
     pub fn inc(&mut self, rid: Rid) {
-        let self_ = &mut self.0;
+        let self_ = &mut self.counts;
         let idx_result = self_.binary_search_by_key(&&rid, |(rid, _)| rid);
-        match idx_result {
+        let count = match idx_result {
             Ok(idx) => {
                 let (_, count) = self_
                     .get_mut(idx)
                     .expect("index returned by `binary_search`");
                 *count += 1;
+                *count
             },
-            Err(idx) => self_.insert(idx, (rid, 1)),
-        }
+            Err(idx) => {
+                self_.insert(idx, (rid, 1));
+                1
+            },
+        };
         debug_assert!(self_.windows(2).all(|w| w[0] <= w[1]));
+        // The delta holds the latest count rather than the increment, so that deltas merge
+        // exactly like full states.
+        match self.delta.binary_search_by_key(&&rid, |(rid, _)| rid) {
+            Ok(idx) => self.delta[idx].1 = count,
+            Err(idx) => self.delta.insert(idx, (rid, count)),
+        }
     }
     pub fn value(&self) -> GCounterInt {
         // TODO: cache the result.
-        self.0.iter().map(|(_, i)| i).sum()
+        self.counts.iter().map(|(_, i)| i).sum()
     }
     pub fn get(&self, rid: &Rid) -> Option<GCounterInt> {
-        let i = self.0.binary_search_by_key(&rid, |(rid, _)| rid).ok()?;
-        let (_, count) = self.0.get(i).expect("index returned by `binary_search`");
+        let i = self
+            .counts
+            .binary_search_by_key(&rid, |(rid, _)| rid)
+            .ok()?;
+        let (_, count) = self
+            .counts
+            .get(i)
+            .expect("index returned by `binary_search`");
         Some(*count)
     }
+    /// Take the delta of all local increments since the last call to `take_delta`.
+    ///
+    /// The delta can be sent to other replicas in place of the full counter, and merged with
+    /// [`Self::merge_delta`] or, once saved, [`ReconcileContainer::merge`].
+    pub fn take_delta(&mut self) -> GCounterDelta {
+        GCounterDelta(std::mem::take(&mut self.delta))
+    }
+    pub fn merge_delta(&mut self, delta: &GCounterDelta) {
+        self.merge_ivec(delta.0.clone())
+    }
+}
+impl PartialEq for GCounter {
+    fn eq(&self, other: &Self) -> bool {
+        // The delta is local replication state, not part of the value.
+        self.counts == other.counts
+    }
+}
+impl Eq for GCounter {}
+/// The entries of a [`GCounter`] changed since a previous state, persisted in the same form as a
+/// `GCounter` such that it can be merged like a full state.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GCounterDelta(pub(crate) IVec);
+impl GCounterDelta {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+#[async_trait]
+impl<S> PersistContainer<S> for GCounterDelta
+where
+    S: ContentStore,
+{
+    async fn open(store: &Arc<S>, cid: &Cid) -> Result<Self, StoreError> {
+        let inner = store.get_owned_unchecked::<IVec>(cid).await?;
+        Ok(Self(inner))
+    }
+    async fn save(&mut self, store: &Arc<S>) -> Result<Cid, StoreError> {
+        store.put::<IVec>(&self.0).await
+    }
+    async fn save_with_cids(
+        &mut self,
+        store: &Arc<S>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<(), StoreError> {
+        store.put_with_cids::<IVec>(&self.0, cids_buf).await
+    }
 }
 impl DescribeContainer for GCounter {
     fn description() -> ContainerDescription {
@@ -67,17 +144,17 @@ where
 {
     async fn open(store: &Arc<S>, cid: &Cid) -> Result<Self, StoreError> {
         let inner = store.get_owned_unchecked::<IVec>(cid).await?;
-        Ok(Self(inner))
+        Ok(Self::from_ivec(inner))
     }
     async fn save(&mut self, store: &Arc<S>) -> Result<Cid, StoreError> {
-        store.put::<IVec>(&self.0).await
+        store.put::<IVec>(&self.counts).await
     }
     async fn save_with_cids(
         &mut self,
         store: &Arc<S>,
         cids_buf: &mut Vec<Cid>,
     ) -> Result<(), StoreError> {
-        store.put_with_cids::<IVec>(&self.0, cids_buf).await
+        store.put_with_cids::<IVec>(&self.counts, cids_buf).await
     }
 }
 #[async_trait]
@@ -87,34 +164,60 @@ where
 {
     async fn merge(&mut self, store: &Arc<S>, other: &Cid) -> Result<(), StoreError> {
         let other = store.get_owned_unchecked::<IVec>(other).await?;
+        self.merge_ivec(other);
+        Ok(())
+    }
+    async fn diff(&mut self, store: &Arc<S>, other: &Cid) -> Result<Self, StoreError> {
+        let other = store.get_owned_unchecked::<IVec>(other).await?;
+        Ok(Self::from_ivec(self.diff_ivec(&other)))
+    }
+}
+impl GCounter {
+    /// Merge the persisted form of another counter into this counter.
+    pub(crate) fn merge_ivec(&mut self, other: IVec) {
         let mut start_idx = 0;
         for (other_rid, other_value) in other {
-            if start_idx >= self.0.len() {
-                self.0.push((other_rid, other_value));
+            if start_idx >= self.counts.len() {
+                self.counts.push((other_rid, other_value));
                 continue;
             }
             // Assume both are sorted, nearby debug_assert helps validate.
-            let idx = self.0[start_idx..].binary_search_by_key(&&other_rid, |(rid, _)| rid);
+            let idx = self.counts[start_idx..]
+                .binary_search_by_key(&&other_rid, |(rid, _)| rid)
+                // The search is over a subslice, so offset back into `self.counts`.
+                .map(|idx| start_idx + idx)
+                .map_err(|idx| start_idx + idx);
             let idx = match idx {
                 Ok(idx) => {
-                    let (_, self_value) = &mut self.0[idx];
+                    let (_, self_value) = &mut self.counts[idx];
                     if other_value > *self_value {
                         *self_value = other_value;
                     }
                     idx
                 },
                 Err(idx) => {
-                    self.0.insert(idx, (other_rid, other_value));
+                    self.counts.insert(idx, (other_rid, other_value));
                     idx
                 },
             };
             start_idx = idx + 1;
         }
-        debug_assert!(self.0.windows(2).all(|w| w[0] <= w[1]));
-        Ok(())
+        debug_assert!(self.counts.windows(2).all(|w| w[0] <= w[1]));
     }
-    async fn diff(&mut self, _store: &Arc<S>, _other: &Cid) -> Result<Self, StoreError> {
-        todo!()
+    /// The entries of this counter that are newer than those of `other`, aka what `other` is
+    /// missing from this counter.
+    pub(crate) fn diff_ivec(&self, other: &IVec) -> IVec {
+        self.counts
+            .iter()
+            .filter(|(rid, value)| {
+                let other_value = other
+                    .binary_search_by_key(&rid, |(rid, _)| rid)
+                    .map(|idx| other[idx].1)
+                    .unwrap_or(0);
+                *value > other_value
+            })
+            .copied()
+            .collect()
     }
 }
 #[cfg(test)]
@@ -142,6 +245,73 @@ pub mod test {
         a.merge(&store, &b_cid).await.unwrap();
         assert_eq!(a.value(), 4);
     }
+    #[tokio::test]
+    async fn merge_offset() {
+        let store = Memory::test();
+        let mut a = GCounter::default_container(&store);
+        a.inc(0.into());
+        a.inc(2.into());
+        a.inc(4.into());
+        let mut b = GCounter::default_container(&store);
+        for rid in [1, 2, 2, 3, 4, 4, 4] {
+            b.inc(rid.into());
+        }
+        let b_cid = b.save(&store).await.unwrap();
+        a.merge(&store, &b_cid).await.unwrap();
+        assert_eq!(
+            a.as_ivec(),
+            &vec![
+                (0.into(), 1),
+                (1.into(), 1),
+                (2.into(), 2),
+                (3.into(), 1),
+                (4.into(), 3)
+            ]
+        );
+    }
+    #[tokio::test]
+    async fn diff() {
+        let store = Memory::test();
+        let mut a = GCounter::default_container(&store);
+        a.inc(1.into());
+        a.inc(2.into());
+        let a_cid = a.save(&store).await.unwrap();
+        let mut b = GCounter::open(&store, &a_cid).await.unwrap();
+        b.inc(2.into());
+        b.inc(3.into());
+        let mut diff = b.diff(&store, &a_cid).await.unwrap();
+        assert_eq!(diff.as_ivec(), &vec![(2.into(), 2), (3.into(), 1)]);
+        let diff_cid = diff.save(&store).await.unwrap();
+        a.merge(&store, &diff_cid).await.unwrap();
+        assert_eq!(a, b);
+        let empty = b
+            .diff(&store, &a.save(&store).await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(empty, GCounter::new());
+    }
+    #[tokio::test]
+    async fn delta() {
+        let store = Memory::test();
+        let mut a = GCounter::default_container(&store);
+        let mut b = GCounter::default_container(&store);
+        a.inc(1.into());
+        a.inc(1.into());
+        b.merge_delta(&a.take_delta());
+        assert_eq!(a, b);
+        assert!(a.take_delta().is_empty());
+        a.inc(1.into());
+        a.inc(2.into());
+        let mut delta = a.take_delta();
+        assert_eq!(delta.0, vec![(1.into(), 3), (2.into(), 1)]);
+        // Deltas are persisted like full states, so they merge like full states.
+        let delta_cid = delta.save(&store).await.unwrap();
+        b.merge(&store, &delta_cid).await.unwrap();
+        assert_eq!(a, b);
+        // Re-delivery is harmless.
+        b.merge_delta(&delta);
+        assert_eq!(b.value(), 4);
+    }
 
     use proptest::collection::size_range;
     use test_strategy::{proptest, Arbitrary};
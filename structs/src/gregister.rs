@@ -0,0 +1,217 @@
+use crate::{
+    gcounter::{GCounter, IVec},
+    vclock::VClock,
+};
+use async_trait::async_trait;
+use fixity_store::{
+    container::{ContainerDescription, DescribeContainer, PersistContainer, ReconcileContainer},
+    content_store::ContentStore,
+    contentid::Cid,
+    deser::{Deserialize, Serialize},
+    deser_ext::DeserExt,
+    replicaid::Rid,
+    store::StoreError,
+};
+use std::sync::Arc;
+
+/// The persisted form of a [`GRegister`], each sibling value with its clock.
+type Siblings<T> = Vec<(IVec, T)>;
+
+/// A multi-value register, where concurrent writes are kept as siblings until a later write
+/// supersedes them.
+///
+/// Each sibling is tagged with a [`VClock`], and a sibling is dropped only once it happens before
+/// another sibling. Resolution of conflicting siblings is left to the application, by writing a
+/// new value with [`GRegister::set`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GRegister<T> {
+    siblings: Vec<(VClock, T)>,
+}
+impl<T> GRegister<T> {
+    pub fn new() -> Self {
+        Self {
+            siblings: Vec::new(),
+        }
+    }
+    /// The current values of the register. More than one value means concurrent writes have
+    /// yet to be resolved.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.siblings.iter().map(|(_, value)| value)
+    }
+    /// Whether the register holds more than one concurrently written value.
+    pub fn is_conflicted(&self) -> bool {
+        self.siblings.len() > 1
+    }
+    /// Write the value as the given replica, superseding all current siblings.
+    pub fn set(&mut self, rid: Rid, value: T) {
+        let mut clock = VClock::new();
+        for (sibling_clock, _) in self.siblings.iter() {
+            clock.join(sibling_clock);
+        }
+        clock.inc(rid);
+        self.siblings = vec![(clock, value)];
+    }
+    /// Merge the siblings of another register into this one, dropping any sibling that happens
+    /// before a sibling of the other register.
+    pub fn join(&mut self, other: Self) {
+        let other = other
+            .siblings
+            .into_iter()
+            .filter(|(other_clock, _)| {
+                !self
+                    .siblings
+                    .iter()
+                    .any(|(clock, _)| other_clock.happens_before(clock) || other_clock == clock)
+            })
+            .collect::<Vec<_>>();
+        self.siblings.retain(|(clock, _)| {
+            !other
+                .iter()
+                .any(|(other_clock, _)| clock.happens_before(other_clock))
+        });
+        self.siblings.extend(other);
+    }
+}
+impl<T> Default for GRegister<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+fn from_siblings<T>(siblings: Siblings<T>) -> Vec<(VClock, T)> {
+    siblings
+        .into_iter()
+        .map(|(clock, value)| (VClock::from(GCounter::from_ivec(clock)), value))
+        .collect()
+}
+impl<T> DescribeContainer for GRegister<T> {
+    fn description() -> ContainerDescription {
+        // TODO: Describe `T`, once params are supported.
+        ContainerDescription {
+            name: "GRegister",
+            params: Default::default(),
+        }
+    }
+}
+#[async_trait]
+impl<T, S> PersistContainer<S> for GRegister<T>
+where
+    S: ContentStore,
+    T: Clone + Send + Sync,
+    Siblings<T>: Serialize + Deserialize,
+{
+    async fn open(store: &Arc<S>, cid: &Cid) -> Result<Self, StoreError> {
+        let siblings = store.get_owned_unchecked::<Siblings<T>>(cid).await?;
+        Ok(Self {
+            siblings: from_siblings(siblings),
+        })
+    }
+    async fn save(&mut self, store: &Arc<S>) -> Result<Cid, StoreError> {
+        store.put::<Siblings<T>>(&self.to_siblings()).await
+    }
+    async fn save_with_cids(
+        &mut self,
+        store: &Arc<S>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<(), StoreError> {
+        store
+            .put_with_cids::<Siblings<T>>(&self.to_siblings(), cids_buf)
+            .await
+    }
+}
+impl<T: Clone> GRegister<T> {
+    // NIT: Cloning into the persisted form is unfortunate, a borrowed repr would avoid it.
+    fn to_siblings(&self) -> Siblings<T> {
+        self.siblings
+            .iter()
+            .map(|(clock, value)| (clock.as_ivec().clone(), value.clone()))
+            .collect()
+    }
+}
+#[async_trait]
+impl<T, S> ReconcileContainer<S> for GRegister<T>
+where
+    S: ContentStore,
+    T: Clone + Send + Sync,
+    Siblings<T>: Serialize + Deserialize,
+{
+    async fn merge(&mut self, store: &Arc<S>, other: &Cid) -> Result<(), StoreError> {
+        let other = store.get_owned_unchecked::<Siblings<T>>(other).await?;
+        self.join(Self {
+            siblings: from_siblings(other),
+        });
+        Ok(())
+    }
+    async fn diff(&mut self, store: &Arc<S>, other: &Cid) -> Result<Self, StoreError> {
+        let other = from_siblings(store.get_owned_unchecked::<Siblings<T>>(other).await?);
+        let siblings = self
+            .siblings
+            .iter()
+            .filter(|(clock, _)| {
+                !other.iter().any(|(other_clock, _)| {
+                    clock.happens_before(other_clock) || clock == other_clock
+                })
+            })
+            .cloned()
+            .collect();
+        Ok(Self { siblings })
+    }
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use fixity_store::stores::memory::Memory;
+
+    fn values(reg: &GRegister<String>) -> Vec<&str> {
+        let mut values = reg.values().map(String::as_str).collect::<Vec<_>>();
+        values.sort();
+        values
+    }
+    #[test]
+    fn siblings() {
+        let mut a = GRegister::<String>::new();
+        a.set(1.into(), "a".into());
+        let mut b = a.clone();
+        a.set(1.into(), "a2".into());
+        b.set(2.into(), "b".into());
+        let mut ab = a.clone();
+        ab.join(b.clone());
+        let mut ba = b.clone();
+        ba.join(a.clone());
+        assert!(ab.is_conflicted());
+        assert_eq!(values(&ab), vec!["a2", "b"]);
+        assert_eq!(values(&ba), vec!["a2", "b"]);
+        // Joining is idempotent.
+        ab.join(b.clone());
+        assert_eq!(values(&ab), vec!["a2", "b"]);
+        // A resolving write supersedes all siblings, including on the other replicas.
+        ab.set(1.into(), "resolved".into());
+        assert!(!ab.is_conflicted());
+        ba.join(ab.clone());
+        assert_eq!(values(&ba), vec!["resolved"]);
+        a.join(ab.clone());
+        assert_eq!(values(&a), vec!["resolved"]);
+    }
+    #[tokio::test]
+    async fn merge_diff() {
+        let store = Memory::test();
+        let mut a = GRegister::<String>::new();
+        a.set(1.into(), "a".into());
+        let a_cid = a.save(&store).await.unwrap();
+        let mut b = GRegister::<String>::open(&store, &a_cid).await.unwrap();
+        assert_eq!(a, b);
+        b.set(2.into(), "b".into());
+        a.set(1.into(), "a2".into());
+        let b_cid = b.save(&store).await.unwrap();
+        let a_cid = a.save(&store).await.unwrap();
+        let mut diff = b.diff(&store, &a_cid).await.unwrap();
+        assert_eq!(values(&diff), vec!["b"]);
+        let diff_cid = diff.save(&store).await.unwrap();
+        let mut c = GRegister::<String>::open(&store, &a_cid).await.unwrap();
+        c.merge(&store, &diff_cid).await.unwrap();
+        a.merge(&store, &b_cid).await.unwrap();
+        assert_eq!(values(&a), vec!["a2", "b"]);
+        assert_eq!(values(&c), values(&a));
+        let diff = a.diff(&store, &a_cid).await.unwrap();
+        assert_eq!(values(&diff), vec!["b"]);
+    }
+}
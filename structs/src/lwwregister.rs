@@ -0,0 +1,189 @@
+use async_trait::async_trait;
+use fixity_store::{
+    container::{ContainerDescription, DescribeContainer, ReconcileContainer},
+    content_store::ContentStore,
+    contentid::Cid,
+    deser::{Deserialize, Serialize},
+    deser_ext::DeserExt,
+    replicaid::Rid,
+    store::StoreError,
+};
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A hybrid logical clock timestamp, a wall clock time in milliseconds paired with a logical
+/// counter to order events within, or behind, the same wall clock time.
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)
+)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hlc {
+    pub wall: u64,
+    pub logical: u32,
+}
+impl Hlc {
+    /// The current wall clock time in milliseconds.
+    pub fn wall_now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
+    /// The next timestamp after this one, given the current wall clock time.
+    ///
+    /// The result is always greater than `self`, even if the wall clock has gone backwards.
+    pub fn tick(&self, wall: u64) -> Self {
+        if wall > self.wall {
+            Self { wall, logical: 0 }
+        } else {
+            Self {
+                wall: self.wall,
+                logical: self.logical + 1,
+            }
+        }
+    }
+}
+/// A last-writer-wins register, where the write with the greatest [`Hlc`] wins and ties are
+/// broken by the greatest [`Rid`].
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)
+)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LwwRegister<T> {
+    value: T,
+    hlc: Hlc,
+    rid: Rid,
+}
+impl<T> LwwRegister<T> {
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+    /// The timestamp and writer of the current value.
+    pub fn stamp(&self) -> (Hlc, Rid) {
+        (self.hlc, self.rid)
+    }
+    /// Write the value as the given replica, timestamped with the current wall clock.
+    pub fn set(&mut self, rid: Rid, value: T) {
+        self.set_at(rid, value, Hlc::wall_now())
+    }
+    /// Write the value as the given replica, timestamped with the given wall clock time in
+    /// milliseconds.
+    ///
+    /// Primarily useful for deterministic tests, prefer [`Self::set`].
+    pub fn set_at(&mut self, rid: Rid, value: T, wall: u64) {
+        self.hlc = self.hlc.tick(wall);
+        self.rid = rid;
+        self.value = value;
+    }
+    /// Merge another register into this one, keeping the latest write.
+    pub fn join(&mut self, other: Self) {
+        if other.stamp() > self.stamp() {
+            *self = other;
+        }
+    }
+}
+impl<T> DescribeContainer for LwwRegister<T> {
+    fn description() -> ContainerDescription {
+        // TODO: Describe `T`, once params are supported.
+        ContainerDescription {
+            name: "LwwRegister",
+            params: Default::default(),
+        }
+    }
+}
+#[async_trait]
+impl<T, S> ReconcileContainer<S> for LwwRegister<T>
+where
+    S: ContentStore,
+    T: Default + Clone + Send + Sync,
+    Self: Serialize + Deserialize,
+{
+    async fn merge(&mut self, store: &Arc<S>, other: &Cid) -> Result<(), StoreError> {
+        let other = store.get_owned_unchecked::<Self>(other).await?;
+        self.join(other);
+        Ok(())
+    }
+    async fn diff(&mut self, store: &Arc<S>, other: &Cid) -> Result<Self, StoreError> {
+        let other = store.get_owned_unchecked::<Self>(other).await?;
+        if self.stamp() > other.stamp() {
+            Ok(self.clone())
+        } else {
+            // A default register loses to any write, so it merges as a no-op.
+            Ok(Self::default())
+        }
+    }
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use fixity_store::{container::PersistContainer, stores::memory::Memory};
+
+    #[test]
+    fn hlc() {
+        let hlc = Hlc::default().tick(10);
+        assert_eq!(
+            hlc,
+            Hlc {
+                wall: 10,
+                logical: 0
+            }
+        );
+        assert_eq!(
+            hlc.tick(5),
+            Hlc {
+                wall: 10,
+                logical: 1
+            },
+            "wall clock went backwards"
+        );
+        assert!(hlc.tick(10) > hlc);
+        assert!(hlc.tick(11) > hlc.tick(10));
+    }
+    #[test]
+    fn join() {
+        let mut a = LwwRegister::<String>::default();
+        let mut b = LwwRegister::<String>::default();
+        a.set_at(1.into(), "a".into(), 10);
+        b.set_at(2.into(), "b".into(), 20);
+        let mut ab = a.clone();
+        ab.join(b.clone());
+        let mut ba = b.clone();
+        ba.join(a.clone());
+        assert_eq!(ab, ba);
+        assert_eq!(ab.get(), "b");
+        // Same timestamp, the greater rid wins.
+        let mut c = LwwRegister::<String>::default();
+        c.set_at(3.into(), "c".into(), 20);
+        ab.join(c.clone());
+        assert_eq!(ab.get(), "c");
+        c.join(b);
+        assert_eq!(c.get(), "c");
+        // A local write after a merge wins, even if the local wall clock is behind.
+        a.join(ab);
+        a.set_at(1.into(), "a2".into(), 15);
+        assert_eq!(a.get(), "a2");
+        assert!(a.stamp().0 > c.stamp().0);
+    }
+    #[tokio::test]
+    async fn merge_diff() {
+        let store = Memory::test();
+        let mut a = LwwRegister::<String>::default();
+        a.set_at(1.into(), "a".into(), 10);
+        let a_cid = a.save(&store).await.unwrap();
+        let mut b = LwwRegister::<String>::open(&store, &a_cid).await.unwrap();
+        assert_eq!(a, b);
+        b.set_at(2.into(), "b".into(), 5);
+        let b_cid = b.save(&store).await.unwrap();
+        let diff = a.diff(&store, &b_cid).await.unwrap();
+        assert_eq!(diff, LwwRegister::default(), "b is newer than a");
+        let mut diff = b.diff(&store, &a_cid).await.unwrap();
+        assert_eq!(diff.get(), "b");
+        let diff_cid = diff.save(&store).await.unwrap();
+        a.merge(&store, &diff_cid).await.unwrap();
+        assert_eq!(a, b);
+    }
+}
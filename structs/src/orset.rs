@@ -0,0 +1,318 @@
+use crate::{
+    chunk::{self, StoredChunks},
+    gcounter::{GCounter, GCounterInt, IVec},
+    vclock::VClock,
+};
+use async_trait::async_trait;
+use fixity_store::{
+    container::{ContainerDescription, DescribeContainer, PersistContainer, ReconcileContainer},
+    content_store::ContentStore,
+    contentid::Cid,
+    deser::{Deserialize, Serialize},
+    deser_ext::DeserExt,
+    replicaid::Rid,
+    store::StoreError,
+};
+use std::{collections::BTreeMap, sync::Arc};
+
+/// A unique identifier of an add, the replica and its clock at the time of the add.
+pub type Dot = (Rid, GCounterInt);
+/// A chunk of the persisted form of an [`ORSet`], elements and their dots sorted by element.
+pub type Chunk<T> = Vec<(T, Vec<Dot>)>;
+/// The root of the persisted form of an [`ORSet`], the causal context and chunk pointers.
+type Root = (IVec, Vec<Cid>);
+
+/// An observed-remove set, where an add concurrent with a remove of the same element wins.
+///
+/// Implemented as an "OR-Set without tombstones", where each element only stores the dots of its
+/// live adds and removals are implied by the causal context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ORSet<T> {
+    /// Every dot this set has observed.
+    clock: VClock,
+    entries: BTreeMap<T, Vec<Dot>>,
+    stored: StoredChunks,
+}
+impl<T> ORSet<T>
+where
+    T: Ord,
+{
+    pub fn new() -> Self {
+        Self {
+            clock: VClock::new(),
+            entries: BTreeMap::new(),
+            stored: StoredChunks::default(),
+        }
+    }
+    pub fn contains(&self, value: &T) -> bool {
+        self.entries.contains_key(value)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.entries.keys()
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// Add the value as the given replica.
+    pub fn add(&mut self, rid: Rid, value: T) {
+        self.clock.inc(rid);
+        let dot = (rid, self.clock.get(&rid));
+        // The add has observed every previous add of this value, so it supersedes them.
+        self.entries.insert(value, vec![dot]);
+    }
+    /// Remove the value, returning whether it was present.
+    ///
+    /// Only the adds observed by this set are removed, concurrent adds of the value on other
+    /// replicas will survive a merge.
+    pub fn remove(&mut self, value: &T) -> bool {
+        self.entries.remove(value).is_some()
+    }
+    /// Merge another set into this one.
+    pub fn join(&mut self, other: Self) {
+        let Self {
+            clock: other_clock,
+            entries: mut other_entries,
+            ..
+        } = other;
+        self.entries.retain(|value, dots| {
+            let other_dots = other_entries.remove(value).unwrap_or_default();
+            // Keep dots both sides have, or that the other side has not observed.
+            dots.retain(|dot| other_dots.contains(dot) || !covers(&other_clock, dot));
+            for dot in other_dots {
+                if !dots.contains(&dot) && !covers(&self.clock, &dot) {
+                    dots.push(dot);
+                }
+            }
+            dots.sort();
+            !dots.is_empty()
+        });
+        // Remaining entries are only in the other side.
+        for (value, mut dots) in other_entries {
+            dots.retain(|dot| !covers(&self.clock, dot));
+            if !dots.is_empty() {
+                self.entries.insert(value, dots);
+            }
+        }
+        self.clock.join(&other_clock);
+    }
+}
+impl<T: Ord> Default for ORSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+fn covers(clock: &VClock, (rid, counter): &Dot) -> bool {
+    clock.get(rid) >= *counter
+}
+impl<T> DescribeContainer for ORSet<T> {
+    fn description() -> ContainerDescription {
+        // TODO: Describe `T`, once params are supported.
+        ContainerDescription {
+            name: "ORSet",
+            params: Default::default(),
+        }
+    }
+}
+impl<T> ORSet<T>
+where
+    T: Ord + Clone + Serialize,
+{
+    /// Split the entries into chunks, with boundaries defined by the content of the elements such
+    /// that an insert or remove only changes the chunk it falls in.
+    fn to_chunks(&self) -> Result<Vec<Chunk<T>>, StoreError> {
+        let mut chunks = Vec::new();
+        let mut chunk = Vec::new();
+        for (value, dots) in self.entries.iter() {
+            chunk.push((value.clone(), dots.clone()));
+            if chunk::is_boundary(value.serialize()?.as_ref()) {
+                chunks.push(std::mem::take(&mut chunk));
+            }
+        }
+        if !chunk.is_empty() {
+            chunks.push(chunk);
+        }
+        Ok(chunks)
+    }
+}
+#[async_trait]
+impl<T, S> PersistContainer<S> for ORSet<T>
+where
+    S: ContentStore,
+    T: Ord + Clone + Send + Sync + Serialize,
+    Chunk<T>: Serialize + Deserialize,
+{
+    async fn open(store: &Arc<S>, cid: &Cid) -> Result<Self, StoreError> {
+        let (clock, chunk_cids) = store.get_owned_unchecked::<Root>(cid).await?;
+        let mut entries = BTreeMap::new();
+        for chunk_cid in chunk_cids.iter() {
+            let chunk = store.get_owned_unchecked::<Chunk<T>>(chunk_cid).await?;
+            entries.extend(chunk);
+        }
+        Ok(Self {
+            clock: VClock::from(GCounter::from_ivec(clock)),
+            entries,
+            stored: StoredChunks::new(&chunk_cids),
+        })
+    }
+    async fn save(&mut self, store: &Arc<S>) -> Result<Cid, StoreError> {
+        let mut cids = Vec::new();
+        self.save_with_cids(store, &mut cids).await?;
+        Ok(cids.pop().expect("root cid written"))
+    }
+    async fn save_with_cids(
+        &mut self,
+        store: &Arc<S>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<(), StoreError> {
+        let chunks = self.to_chunks()?;
+        let chunk_cids = self.stored.save(store, chunks, cids_buf).await?;
+        let root: Root = (self.clock.as_ivec().clone(), chunk_cids);
+        store.put_with_cids(&root, cids_buf).await
+    }
+}
+#[async_trait]
+impl<T, S> ReconcileContainer<S> for ORSet<T>
+where
+    S: ContentStore,
+    T: Ord + Clone + Send + Sync + Serialize,
+    Chunk<T>: Serialize + Deserialize,
+{
+    async fn merge(&mut self, store: &Arc<S>, other: &Cid) -> Result<(), StoreError> {
+        let other = Self::open(store, other).await?;
+        self.join(other);
+        Ok(())
+    }
+    async fn diff(&mut self, store: &Arc<S>, other: &Cid) -> Result<Self, StoreError> {
+        let Self {
+            clock: other_clock,
+            entries: other_entries,
+            ..
+        } = Self::open(store, other).await?;
+        // The context of the diff only needs the dots the other side is missing: adds it has not
+        // observed, and removes of adds it still has.
+        let mut clock = VClock::new();
+        let mut observe = |(rid, counter): &Dot| {
+            while clock.get(rid) < *counter {
+                clock.inc(*rid);
+            }
+        };
+        for dot in self.entries.values().flatten() {
+            if !covers(&other_clock, dot) {
+                observe(dot);
+            }
+        }
+        for (value, other_dots) in other_entries.iter() {
+            let dots = self.entries.get(value);
+            for dot in other_dots {
+                if covers(&self.clock, dot) && !matches!(dots, Some(dots) if dots.contains(dot)) {
+                    observe(dot);
+                }
+            }
+        }
+        // Every element with a dot in the context must be included, otherwise merging the diff
+        // would remove it.
+        let entries = self
+            .entries
+            .iter()
+            .filter(|(_, dots)| dots.iter().any(|dot| covers(&clock, dot)))
+            .map(|(value, dots)| (value.clone(), dots.clone()))
+            .collect();
+        Ok(Self {
+            clock,
+            entries,
+            stored: StoredChunks::default(),
+        })
+    }
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use fixity_store::stores::memory::Memory;
+
+    fn values(set: &ORSet<u32>) -> Vec<u32> {
+        set.iter().copied().collect()
+    }
+    #[test]
+    fn add_remove() {
+        let mut a = ORSet::new();
+        a.add(1.into(), 1);
+        a.add(1.into(), 2);
+        assert!(a.remove(&1));
+        assert!(!a.remove(&1));
+        assert_eq!(values(&a), vec![2]);
+        let mut b = a.clone();
+        // Concurrent add and remove, the add wins.
+        a.remove(&2);
+        b.add(2.into(), 2);
+        let mut ab = a.clone();
+        ab.join(b.clone());
+        let mut ba = b.clone();
+        ba.join(a.clone());
+        assert_eq!(ab, ba);
+        assert_eq!(values(&ab), vec![2]);
+        // An observed remove removes it everywhere.
+        ab.remove(&2);
+        a.join(ab.clone());
+        b.join(ab.clone());
+        assert!(a.is_empty());
+        assert!(b.is_empty());
+    }
+    #[tokio::test]
+    async fn merge_diff() {
+        let store = Memory::test();
+        let mut a = ORSet::new();
+        for i in 0..10 {
+            a.add(1.into(), i);
+        }
+        let a_cid = a.save(&store).await.unwrap();
+        let mut b = ORSet::<u32>::open(&store, &a_cid).await.unwrap();
+        assert_eq!(a, b);
+        b.remove(&3);
+        b.add(2.into(), 4);
+        b.add(2.into(), 20);
+        a.add(1.into(), 30);
+        let b_cid = b.save(&store).await.unwrap();
+        let a_cid = a.save(&store).await.unwrap();
+        let mut diff = b.diff(&store, &a_cid).await.unwrap();
+        // The context is a clock, so the remove of `3` pulls in the adds before it as well.
+        assert_eq!(values(&diff), vec![0, 1, 2, 4, 20]);
+        let diff_cid = diff.save(&store).await.unwrap();
+        let mut via_diff = ORSet::<u32>::open(&store, &a_cid).await.unwrap();
+        via_diff.merge(&store, &diff_cid).await.unwrap();
+        a.merge(&store, &b_cid).await.unwrap();
+        assert_eq!(values(&a), vec![0, 1, 2, 4, 5, 6, 7, 8, 9, 20, 30]);
+        assert_eq!(values(&via_diff), values(&a));
+    }
+    #[tokio::test]
+    async fn chunked() {
+        let store = Memory::test();
+        let mut a = ORSet::new();
+        for i in 0..1_000u32 {
+            a.add(1.into(), i);
+        }
+        let chunks = a.to_chunks().unwrap();
+        assert!(chunks.len() > 1, "large sets are split into chunks");
+        let a_cid = a.save(&store).await.unwrap();
+        let mut b = ORSet::<u32>::open(&store, &a_cid).await.unwrap();
+        assert_eq!(a, b);
+        // Boundaries are defined by content, so a change only affects its own chunk.
+        a.remove(&500);
+        let changed = a
+            .to_chunks()
+            .unwrap()
+            .iter()
+            .filter(|chunk| !chunks.contains(chunk))
+            .count();
+        assert_eq!(changed, 1);
+        // Which is the only chunk written, along with the root.
+        for set in [&mut a, &mut b] {
+            set.remove(&500);
+            let mut cids = Vec::new();
+            set.save_with_cids(&store, &mut cids).await.unwrap();
+            assert_eq!(cids.len(), 2);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use crate::gcounter::{GCounter, GCounterDelta, IVec};
+use async_trait::async_trait;
+use fixity_store::{
+    container::{ContainerDescription, DescribeContainer, PersistContainer, ReconcileContainer},
+    content_store::ContentStore,
+    contentid::Cid,
+    deser_ext::DeserExt,
+    replicaid::Rid,
+    store::StoreError,
+};
+use std::sync::Arc;
+
+/// The persisted form of a [`PNCounter`], the increments and decrements respectively.
+type PNVec = (IVec, IVec);
+
+/// A counter which can be incremented and decremented, built from a pair of [`GCounter`]s.
+#[derive(Debug)]
+pub struct PNCounter {
+    inc: GCounter,
+    dec: GCounter,
+}
+impl PNCounter {
+    pub fn new() -> Self {
+        Self {
+            inc: GCounter::new(),
+            dec: GCounter::new(),
+        }
+    }
+    pub fn inc(&mut self, rid: Rid) {
+        self.inc.inc(rid)
+    }
+    pub fn dec(&mut self, rid: Rid) {
+        self.dec.inc(rid)
+    }
+    pub fn value(&self) -> i64 {
+        i64::from(self.inc.value()) - i64::from(self.dec.value())
+    }
+    /// Take the delta of all local changes since the last call to `take_delta`.
+    ///
+    /// See [`GCounter::take_delta`].
+    pub fn take_delta(&mut self) -> PNCounterDelta {
+        PNCounterDelta {
+            inc: self.inc.take_delta(),
+            dec: self.dec.take_delta(),
+        }
+    }
+    pub fn merge_delta(&mut self, delta: &PNCounterDelta) {
+        self.inc.merge_delta(&delta.inc);
+        self.dec.merge_delta(&delta.dec);
+    }
+}
+/// The changes of a [`PNCounter`] since a previous state, persisted in the same form as a
+/// `PNCounter` such that it can be merged like a full state.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PNCounterDelta {
+    inc: GCounterDelta,
+    dec: GCounterDelta,
+}
+impl PNCounterDelta {
+    pub fn is_empty(&self) -> bool {
+        self.inc.is_empty() && self.dec.is_empty()
+    }
+}
+#[async_trait]
+impl<S> PersistContainer<S> for PNCounterDelta
+where
+    S: ContentStore,
+{
+    async fn open(store: &Arc<S>, cid: &Cid) -> Result<Self, StoreError> {
+        let (inc, dec) = store.get_owned_unchecked::<PNVec>(cid).await?;
+        Ok(Self {
+            inc: GCounterDelta(inc),
+            dec: GCounterDelta(dec),
+        })
+    }
+    async fn save(&mut self, store: &Arc<S>) -> Result<Cid, StoreError> {
+        store
+            .put::<PNVec>(&(self.inc.0.clone(), self.dec.0.clone()))
+            .await
+    }
+    async fn save_with_cids(
+        &mut self,
+        store: &Arc<S>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<(), StoreError> {
+        store
+            .put_with_cids::<PNVec>(&(self.inc.0.clone(), self.dec.0.clone()), cids_buf)
+            .await
+    }
+}
+impl DescribeContainer for PNCounter {
+    fn description() -> ContainerDescription {
+        ContainerDescription {
+            name: "PNCounter",
+            params: Default::default(),
+        }
+    }
+}
+impl Default for PNCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[async_trait]
+impl<S> PersistContainer<S> for PNCounter
+where
+    S: ContentStore,
+{
+    async fn open(store: &Arc<S>, cid: &Cid) -> Result<Self, StoreError> {
+        let (inc, dec) = store.get_owned_unchecked::<PNVec>(cid).await?;
+        Ok(Self {
+            inc: GCounter::from_ivec(inc),
+            dec: GCounter::from_ivec(dec),
+        })
+    }
+    async fn save(&mut self, store: &Arc<S>) -> Result<Cid, StoreError> {
+        // NIT: Cloning to fit the tuple repr is unfortunate, a borrowed repr would avoid it.
+        store
+            .put::<PNVec>(&(self.inc.as_ivec().clone(), self.dec.as_ivec().clone()))
+            .await
+    }
+    async fn save_with_cids(
+        &mut self,
+        store: &Arc<S>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<(), StoreError> {
+        store
+            .put_with_cids::<PNVec>(
+                &(self.inc.as_ivec().clone(), self.dec.as_ivec().clone()),
+                cids_buf,
+            )
+            .await
+    }
+}
+#[async_trait]
+impl<S> ReconcileContainer<S> for PNCounter
+where
+    S: ContentStore,
+{
+    async fn merge(&mut self, store: &Arc<S>, other: &Cid) -> Result<(), StoreError> {
+        let (inc, dec) = store.get_owned_unchecked::<PNVec>(other).await?;
+        self.inc.merge_ivec(inc);
+        self.dec.merge_ivec(dec);
+        Ok(())
+    }
+    async fn diff(&mut self, store: &Arc<S>, other: &Cid) -> Result<Self, StoreError> {
+        let (inc, dec) = store.get_owned_unchecked::<PNVec>(other).await?;
+        Ok(Self {
+            inc: GCounter::from_ivec(self.inc.diff_ivec(&inc)),
+            dec: GCounter::from_ivec(self.dec.diff_ivec(&dec)),
+        })
+    }
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use fixity_store::{container::DefaultContainer, stores::memory::Memory};
+
+    #[tokio::test]
+    async fn poc() {
+        let store = Memory::test();
+        let mut a = PNCounter::default_container(&store);
+        a.inc(0.into());
+        assert_eq!(a.value(), 1);
+        a.dec(1.into());
+        a.dec(1.into());
+        assert_eq!(a.value(), -1, "decrements below zero are representable");
+        let cid = a.save(&store).await.unwrap();
+        let b = PNCounter::open(&store, &cid).await.unwrap();
+        assert_eq!(b.value(), -1);
+    }
+    #[tokio::test]
+    async fn merge() {
+        let store = Memory::test();
+        let mut a = PNCounter::default_container(&store);
+        a.inc(0.into());
+        a.inc(0.into());
+        let mut b = PNCounter::default_container(&store);
+        b.dec(1.into());
+        b.dec(1.into());
+        b.dec(1.into());
+        let b_cid = b.save(&store).await.unwrap();
+        a.merge(&store, &b_cid).await.unwrap();
+        assert_eq!(a.value(), -1);
+        // Merging is idempotent.
+        a.merge(&store, &b_cid).await.unwrap();
+        assert_eq!(a.value(), -1);
+        let a_cid = a.save(&store).await.unwrap();
+        b.merge(&store, &a_cid).await.unwrap();
+        assert_eq!(b.value(), -1);
+    }
+    #[tokio::test]
+    async fn diff() {
+        let store = Memory::test();
+        let mut a = PNCounter::default_container(&store);
+        a.inc(0.into());
+        a.dec(1.into());
+        let a_cid = a.save(&store).await.unwrap();
+        let mut b = PNCounter::open(&store, &a_cid).await.unwrap();
+        b.inc(0.into());
+        b.dec(2.into());
+        b.dec(2.into());
+        let mut diff = b.diff(&store, &a_cid).await.unwrap();
+        assert_eq!(diff.inc.as_ivec(), &vec![(0.into(), 2)]);
+        assert_eq!(diff.dec.as_ivec(), &vec![(2.into(), 2)]);
+        // Merging the diff brings `a` up to date with `b`.
+        let diff_cid = diff.save(&store).await.unwrap();
+        a.merge(&store, &diff_cid).await.unwrap();
+        assert_eq!(a.value(), b.value());
+        assert_eq!(a.value(), -1);
+    }
+    #[tokio::test]
+    async fn delta() {
+        let store = Memory::test();
+        let mut a = PNCounter::default_container(&store);
+        let mut b = PNCounter::default_container(&store);
+        a.inc(1.into());
+        a.dec(1.into());
+        a.dec(1.into());
+        let mut delta = a.take_delta();
+        assert!(a.take_delta().is_empty());
+        let delta_cid = delta.save(&store).await.unwrap();
+        b.merge(&store, &delta_cid).await.unwrap();
+        assert_eq!(b.value(), -1);
+        b.dec(2.into());
+        a.merge_delta(&b.take_delta());
+        assert_eq!(a.value(), -2);
+    }
+}
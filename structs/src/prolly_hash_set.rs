@@ -0,0 +1,279 @@
+use crate::prolly_tree::{cursor_diff::Diff, roller::Config as RollerConfig, Node, ProllyTree};
+use async_trait::async_trait;
+use fixity_store::{
+    container::{ContainerDescription, DescribeContainer, PersistContainer, ReconcileContainer},
+    content_store::ContentStore,
+    contentid::{Cid, ContentId},
+    deser::{Deserialize, Serialize},
+    store::StoreError,
+};
+use futures::TryStreamExt;
+use std::sync::Arc;
+
+/// The key of an item within a [`ProllyHashSet`], the content hash of the item.
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)
+)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HashKey(Cid);
+impl HashKey {
+    /// Hash the serialized item with the Cid hash.
+    pub fn hash<T: Serialize>(item: &T) -> Result<Self, StoreError> {
+        Ok(Self(Cid::hash(item.serialize()?.as_ref())))
+    }
+}
+/// An unordered set stored as a [`ProllyTree`] keyed by the hash of each item, such that the same
+/// items always produce the same set regardless of insertion order.
+#[derive(Debug)]
+pub struct ProllyHashSet<T> {
+    tree: ProllyTree<HashKey, T>,
+}
+impl<T> ProllyHashSet<T> {
+    pub fn new() -> Self {
+        Self::with_roller(RollerConfig::default())
+    }
+    pub fn with_roller(roller_config: RollerConfig) -> Self {
+        Self {
+            tree: ProllyTree::with_roller(roller_config),
+        }
+    }
+}
+impl<T> ProllyHashSet<T>
+where
+    T: Serialize,
+{
+    pub fn insert(&mut self, item: T) -> Result<(), StoreError> {
+        self.tree.insert(HashKey::hash(&item)?, item);
+        Ok(())
+    }
+    pub fn remove(&mut self, item: &T) -> Result<(), StoreError> {
+        self.tree.remove(HashKey::hash(item)?);
+        Ok(())
+    }
+}
+impl<T> ProllyHashSet<T>
+where
+    T: Clone,
+{
+    pub async fn contains<S>(&self, store: &Arc<S>, item: &T) -> Result<bool, StoreError>
+    where
+        S: ContentStore,
+        T: Serialize,
+        Node<HashKey, T>: Deserialize,
+    {
+        Ok(self.tree.get(store, &HashKey::hash(item)?).await?.is_some())
+    }
+    /// Every item in the set, ordered by [`HashKey`].
+    pub async fn to_vec<S>(&self, store: &Arc<S>) -> Result<Vec<T>, StoreError>
+    where
+        S: ContentStore,
+        Node<HashKey, T>: Deserialize,
+    {
+        let entries = self.tree.range(store, ..).await?;
+        Ok(entries.into_iter().map(|(_, item)| item).collect())
+    }
+}
+impl<T> Default for ProllyHashSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T> DescribeContainer for ProllyHashSet<T> {
+    fn description() -> ContainerDescription {
+        // TODO: Describe `T`, once params are supported.
+        ContainerDescription {
+            name: "ProllyHashSet",
+            params: Default::default(),
+        }
+    }
+}
+#[async_trait]
+impl<T, S> PersistContainer<S> for ProllyHashSet<T>
+where
+    S: ContentStore,
+    T: Send + Sync,
+    Node<HashKey, T>: Serialize + Deserialize,
+    (HashKey, T): Serialize,
+{
+    async fn open(store: &Arc<S>, cid: &Cid) -> Result<Self, StoreError> {
+        Ok(Self {
+            tree: ProllyTree::open(store, cid).await?,
+        })
+    }
+    async fn save(&mut self, store: &Arc<S>) -> Result<Cid, StoreError> {
+        let mut cids = Vec::new();
+        self.save_with_cids(store, &mut cids).await?;
+        Ok(cids.pop().expect("root cid written"))
+    }
+    async fn save_with_cids(
+        &mut self,
+        store: &Arc<S>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<(), StoreError> {
+        self.tree.save_with_cids(store, cids_buf).await
+    }
+}
+#[async_trait]
+impl<T, S> ReconcileContainer<S> for ProllyHashSet<T>
+where
+    S: ContentStore,
+    T: Clone + PartialEq + Send + Sync,
+    Node<HashKey, T>: Serialize + Deserialize,
+    (HashKey, T): Serialize,
+{
+    /// The union of both sets.
+    ///
+    /// Only the subtrees the sets do not share are read.
+    async fn merge(&mut self, store: &Arc<S>, other: &Cid) -> Result<(), StoreError> {
+        let theirs = ProllyTree::<HashKey, T>::open(store, other).await?;
+        let mut diffs = Box::pin(theirs.diff_stream(store, &self.tree));
+        while let Some(diff) = diffs.try_next().await? {
+            // Items only in this set are kept, and an item hashes to a single key so is never
+            // changed.
+            if let Diff::Added(key, item) = diff {
+                self.tree.insert(key, item);
+            }
+        }
+        // Items removed since the last save are still stored, so they are missing from the diff.
+        let removed = self.tree.removed().copied().collect::<Vec<_>>();
+        for key in removed {
+            if let Some(item) = theirs.get(store, &key).await? {
+                self.tree.insert(key, item);
+            }
+        }
+        Ok(())
+    }
+    /// The items of this set missing from `other`.
+    async fn diff(&mut self, store: &Arc<S>, other: &Cid) -> Result<Self, StoreError> {
+        Ok(Self {
+            tree: self.tree.diff(store, other).await?,
+        })
+    }
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::prolly_tree::{cursor_diff::test::CountingStore, test::TEST_PATTERN};
+    use fixity_store::stores::memory::Memory;
+    use proptest::collection::vec;
+    use std::sync::atomic::Ordering;
+    use test_strategy::proptest;
+
+    fn test_set() -> ProllyHashSet<u32> {
+        ProllyHashSet::with_roller(RollerConfig::with_pattern(TEST_PATTERN))
+    }
+    #[tokio::test]
+    async fn poc() {
+        let store = Memory::test();
+        let mut set = test_set();
+        for i in 0..200 {
+            set.insert(i).unwrap();
+        }
+        set.remove(&5).unwrap();
+        assert!(set.contains(&store, &4).await.unwrap());
+        assert!(!set.contains(&store, &5).await.unwrap());
+        let cid = set.save(&store).await.unwrap();
+        let reopened = ProllyHashSet::<u32>::open(&store, &cid).await.unwrap();
+        assert!(reopened.contains(&store, &4).await.unwrap());
+        assert!(!reopened.contains(&store, &5).await.unwrap());
+        let mut items = reopened.to_vec(&store).await.unwrap();
+        items.sort_unstable();
+        assert_eq!(items, (0..200).filter(|i| *i != 5).collect::<Vec<_>>());
+    }
+    #[tokio::test]
+    async fn merge_union() {
+        let store = Memory::test();
+        let mut a = test_set();
+        let mut b = test_set();
+        for i in 0..100 {
+            a.insert(i).unwrap();
+        }
+        for i in 50..150 {
+            b.insert(i).unwrap();
+        }
+        a.save(&store).await.unwrap();
+        let b_cid = b.save(&store).await.unwrap();
+        let a_cid = a.save(&store).await.unwrap();
+        let mut diff = a.diff(&store, &b_cid).await.unwrap();
+        let mut items = diff.to_vec(&store).await.unwrap();
+        items.sort_unstable();
+        assert_eq!(items, (0..50).collect::<Vec<_>>());
+        let diff_cid = diff.save(&store).await.unwrap();
+        b.merge(&store, &diff_cid).await.unwrap();
+        a.merge(&store, &b_cid).await.unwrap();
+        assert_eq!(
+            a.save(&store).await.unwrap(),
+            b.save(&store).await.unwrap(),
+            "merged sets converge"
+        );
+        let mut items = a.to_vec(&store).await.unwrap();
+        items.sort_unstable();
+        assert_eq!(items, (0..150).collect::<Vec<_>>());
+        // Merging a subset changes nothing.
+        let mut cids = Vec::new();
+        a.merge(&store, &a_cid).await.unwrap();
+        a.save_with_cids(&store, &mut cids).await.unwrap();
+        assert_eq!(cids.len(), 1, "only the root is rewritten");
+    }
+    #[tokio::test]
+    async fn merge_skips_shared_items() {
+        let store = Arc::new(CountingStore::default());
+        let mut a = test_set();
+        for i in 0..10_000 {
+            a.insert(i).unwrap();
+        }
+        let mut cids = Vec::new();
+        a.save_with_cids(&store, &mut cids).await.unwrap();
+        let mut b = ProllyHashSet::<u32>::open(&store, cids.last().unwrap())
+            .await
+            .unwrap();
+        b.insert(10_000).unwrap();
+        let b_cid = b.save(&store).await.unwrap();
+        // Items removed and not yet saved are restored by the union.
+        a.remove(&5).unwrap();
+        store.reads.store(0, Ordering::Relaxed);
+        a.merge(&store, &b_cid).await.unwrap();
+        let reads = store.reads.load(Ordering::Relaxed);
+        assert!(
+            reads < cids.len() / 10,
+            "{reads} of {} blocks read",
+            cids.len()
+        );
+        assert!(a.contains(&store, &5).await.unwrap());
+        assert!(a.contains(&store, &10_000).await.unwrap());
+        assert_eq!(a.save(&store).await.unwrap(), b_cid);
+        let mut diff = b.diff(&store, cids.last().unwrap()).await.unwrap();
+        assert_eq!(diff.to_vec(&store).await.unwrap(), vec![10_000]);
+    }
+    #[proptest]
+    fn history_independent(
+        #[strategy(vec(0..1_000u32, 0..300))] items: Vec<u32>,
+        #[strategy(vec(0..1_000u32, 0..100))] removed: Vec<u32>,
+    ) {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async move {
+            let store = Memory::test();
+            let mut incremental = test_set();
+            for chunk in items.chunks(50) {
+                for item in chunk {
+                    incremental.insert(*item).unwrap();
+                }
+                incremental.save(&store).await.unwrap();
+            }
+            for item in removed.iter() {
+                incremental.remove(item).unwrap();
+            }
+            let mut created = test_set();
+            for item in items.iter().rev().filter(|item| !removed.contains(item)) {
+                created.insert(*item).unwrap();
+            }
+            assert_eq!(
+                incremental.save(&store).await.unwrap(),
+                created.save(&store).await.unwrap()
+            );
+        });
+    }
+}
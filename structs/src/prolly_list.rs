@@ -0,0 +1,723 @@
+use crate::prolly_tree::{
+    cursor_create::Builder,
+    cursor_read::{height, DEFAULT_PREFETCH},
+    roller::{Config as RollerConfig, Roller},
+    TreeNode,
+};
+use async_trait::async_trait;
+use fixity_store::{
+    container::{ContainerDescription, DescribeContainer, PersistContainer, ReconcileContainer},
+    content_store::ContentStore,
+    contentid::Cid,
+    deser::{Deserialize, Serialize},
+    deser_ext::DeserExt,
+    store::StoreError,
+};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use std::{
+    marker::PhantomData,
+    mem,
+    ops::{Bound, Range, RangeBounds},
+    sync::Arc,
+};
+
+/// A single block of a prolly list.
+///
+/// Each branch entry holds the number of items under the child node, allowing lookups by index
+/// without reading the nodes before it.
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)
+)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node<T> {
+    Branch(Vec<(u64, Cid)>),
+    Leaf(Vec<T>),
+}
+impl<T> TreeNode for Node<T> {
+    type Item = T;
+    type Key = u64;
+    fn leaf(items: Vec<T>) -> Self {
+        Self::Leaf(items)
+    }
+    fn branch(entries: Vec<(u64, Cid)>) -> Self {
+        Self::Branch(entries)
+    }
+    fn entries(&self) -> Option<&[(u64, Cid)]> {
+        match self {
+            Self::Branch(entries) => Some(entries),
+            Self::Leaf(_) => None,
+        }
+    }
+    fn leaf_key(items: &[T]) -> u64 {
+        items.len() as u64
+    }
+    fn branch_key(entries: &[(u64, Cid)]) -> u64 {
+        entries.iter().map(|(count, _)| count).sum()
+    }
+    fn roll(roller: &mut Roller, bytes: &[u8]) -> bool {
+        // Unlike the keys of a tree, items may repeat, so every item since the start of the node
+        // is rolled rather than each item on its own.
+        roller.roll_bytes(bytes)
+    }
+}
+/// The persisted form of a [`ProllyList`], metadata pointing to the root [`Node`] of the list.
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)
+)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Root {
+    /// The root node of the list, or `None` if the list is empty.
+    pub node: Option<Cid>,
+    pub len: u64,
+    /// The roller the nodes of the list were chunked with, used for every later edit.
+    pub roller_config: RollerConfig,
+}
+/// An ordered list stored as a prolly tree indexed by position, where node boundaries are defined
+/// by the content of the items such that edits share every unchanged node with previous versions.
+///
+/// Pushed items are buffered in memory until the next edit or save, every other edit is written
+/// immediately.
+#[derive(Debug)]
+pub struct ProllyList<T> {
+    roller_config: RollerConfig,
+    node: Option<Cid>,
+    /// The number of items under `node`.
+    len: u64,
+    /// Items pushed since the last write.
+    pending: Vec<T>,
+    /// Nodes written since the last save.
+    written: Vec<Cid>,
+    /// The most node writes to have in flight at once.
+    concurrency: usize,
+}
+impl<T> ProllyList<T> {
+    pub fn new() -> Self {
+        Self::with_roller(RollerConfig::default())
+    }
+    pub fn with_roller(roller_config: RollerConfig) -> Self {
+        Self {
+            roller_config,
+            node: None,
+            len: 0,
+            pending: Vec::new(),
+            written: Vec::new(),
+            concurrency: 1,
+        }
+    }
+    /// Write up to `writes` nodes to the store concurrently, rather than one at a time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `writes` is zero.
+    pub fn with_concurrency(mut self, writes: usize) -> Self {
+        assert!(writes > 0, "concurrency must allow at least one write");
+        self.concurrency = writes;
+        self
+    }
+    pub fn len(&self) -> usize {
+        self.len as usize + self.pending.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn push(&mut self, item: T) {
+        self.pending.push(item);
+    }
+    fn builder<'s, S>(&self, store: &'s Arc<S>) -> Builder<'s, S, Node<T>> {
+        let mut builder =
+            Builder::new(store, self.roller_config).with_concurrency(self.concurrency);
+        builder.record_cids();
+        builder
+    }
+}
+impl<T> ProllyList<T>
+where
+    T: Clone,
+{
+    pub async fn get<S>(&self, store: &Arc<S>, index: usize) -> Result<Option<T>, StoreError>
+    where
+        S: ContentStore,
+        Node<T>: Deserialize,
+    {
+        if index >= self.len as usize {
+            return Ok(self.pending.get(index - self.len as usize).cloned());
+        }
+        let mut cid = self.node.expect("index within stored items");
+        let mut index = index as u64;
+        loop {
+            match store.get_owned_unchecked::<Node<T>>(&cid).await? {
+                Node::Branch(entries) => {
+                    let (count, child) = entries
+                        .into_iter()
+                        .find(|(count, _)| {
+                            let found = index < *count;
+                            if !found {
+                                index -= count;
+                            }
+                            found
+                        })
+                        .expect("index within child counts");
+                    debug_assert!(index < count);
+                    cid = child;
+                },
+                Node::Leaf(mut items) => return Ok(Some(items.swap_remove(index as usize))),
+            }
+        }
+    }
+    /// The items within the given range of indexes, in order.
+    ///
+    /// Only the nodes overlapping the range are read.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds.
+    pub async fn range<S, R>(&self, store: &Arc<S>, range: R) -> Result<Vec<T>, StoreError>
+    where
+        S: ContentStore,
+        R: RangeBounds<usize>,
+        Node<T>: Deserialize,
+    {
+        self.stream(store, range).try_collect().await
+    }
+    /// Stream the items within the given range of indexes, in order.
+    ///
+    /// Leaves are read as the stream is polled, up to [`DEFAULT_PREFETCH`] leaves ahead, such
+    /// that a large list is never held in memory whole. Only the nodes overlapping the range are
+    /// read.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn stream<'a, S, R>(
+        &'a self,
+        store: &'a Arc<S>,
+        range: R,
+    ) -> impl Stream<Item = Result<T, StoreError>> + 'a
+    where
+        S: ContentStore,
+        R: RangeBounds<usize>,
+        Node<T>: Deserialize,
+    {
+        let Range { start, end } = to_range(range, self.len());
+        let stored_len = self.len as usize;
+        let stored = start.min(stored_len) as u64..end.min(stored_len) as u64;
+        let leaves = Leaves {
+            store,
+            root: self.node.filter(|_| !stored.is_empty()),
+            stack: Vec::new(),
+            range: stored.clone(),
+            _item: PhantomData,
+        };
+        let pending =
+            &self.pending[start.max(stored_len) - stored_len..end.max(stored_len) - stored_len];
+        stream::try_unfold(leaves, |mut leaves| async move {
+            Ok(leaves.next().await?.map(|leaf| (leaf, leaves)))
+        })
+        .map_ok(move |(cid, offset)| async move {
+            let node = store.get_owned_unchecked::<Node<T>>(&cid).await?;
+            Ok::<_, StoreError>((node, offset))
+        })
+        .try_buffered(DEFAULT_PREFETCH)
+        .map_ok(move |(node, offset)| {
+            let items = match node {
+                Node::Leaf(items) => items,
+                Node::Branch(_) => unreachable!("nodes at the bottom of the list are leaves"),
+            };
+            let stored = stored.clone();
+            stream::iter(
+                items
+                    .into_iter()
+                    .zip(offset..)
+                    .filter(move |(_, i)| stored.contains(i))
+                    .map(|(item, _)| Ok(item)),
+            )
+        })
+        .try_flatten()
+        .chain(stream::iter(pending.iter().cloned().map(Ok)))
+    }
+    pub async fn to_vec<S>(&self, store: &Arc<S>) -> Result<Vec<T>, StoreError>
+    where
+        S: ContentStore,
+        Node<T>: Deserialize,
+    {
+        self.range(store, ..).await
+    }
+}
+impl<T> ProllyList<T>
+where
+    T: Serialize + Send + Sync,
+    Node<T>: Serialize + Deserialize,
+{
+    /// Replace the items within the given range of indexes with `items`, returning the removed
+    /// items.
+    ///
+    /// Only the nodes overlapping the range are rewritten.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds.
+    pub async fn splice<S, R, I>(
+        &mut self,
+        store: &Arc<S>,
+        range: R,
+        items: I,
+    ) -> Result<Vec<T>, StoreError>
+    where
+        S: ContentStore,
+        R: RangeBounds<usize>,
+        I: IntoIterator<Item = T>,
+    {
+        let range = to_range(range, self.len());
+        self.flush(store).await?;
+        let items = items.into_iter().collect::<Vec<_>>();
+        let inserted = items.len() as u64;
+        let (node, removed) = Splice {
+            store,
+            builder: self.builder(store),
+            range: range.start as u64..range.end as u64,
+            items,
+        }
+        .apply(self.node, &mut self.written)
+        .await?;
+        self.node = node;
+        self.len = self.len + inserted - removed.len() as u64;
+        Ok(removed)
+    }
+    /// Write any pushed items.
+    async fn flush<S>(&mut self, store: &Arc<S>) -> Result<(), StoreError>
+    where
+        S: ContentStore,
+    {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = mem::take(&mut self.pending);
+        let pending_len = pending.len() as u64;
+        let (node, _) = Splice {
+            store,
+            builder: self.builder(store),
+            range: self.len..self.len,
+            items: pending,
+        }
+        .apply(self.node, &mut self.written)
+        .await?;
+        self.node = node;
+        self.len += pending_len;
+        Ok(())
+    }
+}
+fn to_range<R: RangeBounds<usize>>(range: R, len: usize) -> Range<usize> {
+    let start = match range.start_bound() {
+        Bound::Included(&i) => i,
+        Bound::Excluded(&i) => i + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&i) => i + 1,
+        Bound::Excluded(&i) => i,
+        Bound::Unbounded => len,
+    };
+    assert!(
+        start <= end && end <= len,
+        "range {}..{} out of bounds of {}",
+        start,
+        end,
+        len
+    );
+    start..end
+}
+impl<T> Default for ProllyList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T> DescribeContainer for ProllyList<T> {
+    fn description() -> ContainerDescription {
+        // TODO: Describe `T`, once params are supported.
+        ContainerDescription {
+            name: "ProllyList",
+            params: Default::default(),
+        }
+    }
+}
+#[async_trait]
+impl<T, S> PersistContainer<S> for ProllyList<T>
+where
+    S: ContentStore,
+    T: Serialize + Send + Sync,
+    Node<T>: Serialize + Deserialize,
+{
+    async fn open(store: &Arc<S>, cid: &Cid) -> Result<Self, StoreError> {
+        let Root {
+            node,
+            len,
+            roller_config,
+        } = store.get_owned_unchecked::<Root>(cid).await?;
+        Ok(Self {
+            node,
+            len,
+            ..Self::with_roller(roller_config)
+        })
+    }
+    async fn save(&mut self, store: &Arc<S>) -> Result<Cid, StoreError> {
+        let mut cids = Vec::new();
+        self.save_with_cids(store, &mut cids).await?;
+        Ok(cids.pop().expect("root cid written"))
+    }
+    async fn save_with_cids(
+        &mut self,
+        store: &Arc<S>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<(), StoreError> {
+        self.flush(store).await?;
+        cids_buf.append(&mut self.written);
+        let root = Root {
+            node: self.node,
+            len: self.len,
+            roller_config: self.roller_config,
+        };
+        store.put_with_cids(&root, cids_buf).await
+    }
+}
+#[async_trait]
+impl<T, S> ReconcileContainer<S> for ProllyList<T>
+where
+    S: ContentStore,
+    T: Serialize + Send + Sync,
+    Node<T>: Serialize + Deserialize,
+{
+    async fn merge(&mut self, _: &Arc<S>, _: &Cid) -> Result<(), StoreError> {
+        // NIT: Positions are not stable across replicas, see `Sequence` for a mergeable list.
+        Err(StoreError::UnmergableType)
+    }
+    async fn diff(&mut self, _: &Arc<S>, _: &Cid) -> Result<Self, StoreError> {
+        Err(StoreError::UndiffableType)
+    }
+}
+/// The leaf nodes of a list overlapping a range of indexes, in order, with the index of the first
+/// item of each.
+struct Leaves<'s, S, T> {
+    store: &'s Arc<S>,
+    /// The root node, until the walk starts.
+    root: Option<Cid>,
+    /// The nodes to walk, their height and the index of their first item, in reverse order.
+    stack: Vec<(Cid, usize, u64)>,
+    range: Range<u64>,
+    _item: PhantomData<T>,
+}
+impl<'s, S, T> Leaves<'s, S, T>
+where
+    S: ContentStore,
+    Node<T>: Deserialize,
+{
+    async fn next(&mut self) -> Result<Option<(Cid, u64)>, StoreError> {
+        if let Some(root) = self.root.take() {
+            let height = height::<S, Node<T>>(self.store, root).await?;
+            self.stack.push((root, height, 0));
+        }
+        while let Some((cid, height, offset)) = self.stack.pop() {
+            if height == 0 {
+                return Ok(Some((cid, offset)));
+            }
+            let entries = match self.store.get_owned_unchecked::<Node<T>>(&cid).await? {
+                Node::Branch(entries) => entries,
+                Node::Leaf(_) => unreachable!("nodes above the bottom of the list are branches"),
+            };
+            let mut children = Vec::new();
+            let mut child_offset = offset;
+            for (count, cid) in entries {
+                let child_end = child_offset + count;
+                if child_offset < self.range.end && self.range.start < child_end {
+                    children.push((cid, height - 1, child_offset));
+                }
+                child_offset = child_end;
+            }
+            self.stack.extend(children.into_iter().rev());
+        }
+        Ok(None)
+    }
+}
+/// Replace a range of an existing list, reusing every node outside of the range.
+struct Splice<'s, S, T> {
+    store: &'s Arc<S>,
+    builder: Builder<'s, S, Node<T>>,
+    range: Range<u64>,
+    items: Vec<T>,
+}
+/// A node of the existing list waiting to be reused or rebuilt.
+struct Pending {
+    cid: Cid,
+    height: usize,
+    /// The number of items under the node, or `None` for the root.
+    count: Option<u64>,
+    /// The index of the first item under the node.
+    offset: u64,
+}
+impl<'s, S, T> Splice<'s, S, T>
+where
+    S: ContentStore,
+    T: Serialize + Send + Sync,
+    Node<T>: Serialize + Deserialize,
+{
+    async fn apply(
+        mut self,
+        root: Option<Cid>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<(Option<Cid>, Vec<T>), StoreError> {
+        let root = match root {
+            _ if self.range.is_empty() && self.items.is_empty() => return Ok((root, Vec::new())),
+            Some(root) => root,
+            None => {
+                for item in mem::take(&mut self.items) {
+                    self.builder.push(item).await?;
+                }
+                return Ok((self.builder.finish(cids_buf).await?, Vec::new()));
+            },
+        };
+        let Range { start, end } = self.range;
+        let mut removed = Vec::new();
+        let mut stack: Vec<Pending> = vec![Pending {
+            cid: root,
+            height: height::<S, Node<T>>(self.store, root).await?,
+            count: None,
+            offset: 0,
+        }];
+        while let Some(pending) = stack.pop() {
+            if let Some(count) = pending.count {
+                // Nodes ending at the start of the range are also rebuilt, as items inserted at
+                // the start of the range may continue the node.
+                let touched = pending.offset <= end && start <= pending.offset + count;
+                if !touched && self.builder.is_aligned(pending.height) {
+                    self.builder
+                        .push_node(pending.height, count, pending.cid)
+                        .await?;
+                    continue;
+                }
+            }
+            match self
+                .store
+                .get_owned_unchecked::<Node<T>>(&pending.cid)
+                .await?
+            {
+                Node::Branch(entries) => {
+                    let mut offset = pending.offset;
+                    let mut children = Vec::with_capacity(entries.len());
+                    for (count, cid) in entries {
+                        children.push(Pending {
+                            cid,
+                            height: pending.height - 1,
+                            count: Some(count),
+                            offset,
+                        });
+                        offset += count;
+                    }
+                    stack.extend(children.into_iter().rev());
+                },
+                Node::Leaf(leaf) => {
+                    for (i, item) in (pending.offset..).zip(leaf) {
+                        if i == start {
+                            self.push_items().await?;
+                        }
+                        if (start..end).contains(&i) {
+                            removed.push(item);
+                        } else {
+                            self.builder.push(item).await?;
+                        }
+                    }
+                },
+            }
+        }
+        // Items inserted at the end of the list.
+        self.push_items().await?;
+        Ok((self.builder.finish(cids_buf).await?, removed))
+    }
+    async fn push_items(&mut self) -> Result<(), StoreError> {
+        for item in mem::take(&mut self.items) {
+            self.builder.push(item).await?;
+        }
+        Ok(())
+    }
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::prolly_tree::{cursor_diff::test::CountingStore, test::TEST_PATTERN};
+    use fixity_store::stores::memory::Memory;
+    use proptest::collection::vec;
+    use std::sync::atomic::Ordering;
+    use test_strategy::proptest;
+
+    fn test_list() -> ProllyList<u32> {
+        ProllyList::with_roller(RollerConfig::with_pattern(TEST_PATTERN))
+    }
+    #[tokio::test]
+    async fn poc() {
+        let store = Memory::test();
+        let mut list = test_list();
+        for i in 0..2_000 {
+            list.push(i);
+        }
+        assert_eq!(list.len(), 2_000);
+        assert_eq!(list.get(&store, 1_500).await.unwrap(), Some(1_500));
+        let cid = list.save(&store).await.unwrap();
+        assert_eq!(list.get(&store, 1_500).await.unwrap(), Some(1_500));
+        assert_eq!(list.get(&store, 2_000).await.unwrap(), None);
+        let removed = list.splice(&store, 10..13, [0, 0]).await.unwrap();
+        assert_eq!(removed, vec![10, 11, 12]);
+        assert_eq!(list.len(), 1_999);
+        assert_eq!(
+            list.range(&store, 8..14).await.unwrap(),
+            vec![8, 9, 0, 0, 13, 14]
+        );
+        list.push(2_000);
+        assert_eq!(
+            list.range(&store, 1_997..).await.unwrap(),
+            vec![1_998, 1_999, 2_000]
+        );
+        let reopened = ProllyList::<u32>::open(&store, &cid).await.unwrap();
+        assert_eq!(reopened.len(), 2_000);
+        assert_eq!(
+            reopened.to_vec(&store).await.unwrap(),
+            (0..2_000).collect::<Vec<_>>()
+        );
+    }
+    #[tokio::test]
+    async fn structural_sharing() {
+        let store = Memory::test();
+        let mut list = test_list();
+        for i in 0..10_000 {
+            list.push(i);
+        }
+        let mut cids = Vec::new();
+        list.save_with_cids(&store, &mut cids).await.unwrap();
+        let created = cids.len();
+        list.splice(&store, 5_000..5_001, [0, 1, 2]).await.unwrap();
+        let mut cids = Vec::new();
+        list.save_with_cids(&store, &mut cids).await.unwrap();
+        assert!(
+            cids.len() < created / 10,
+            "{} of {} blocks written",
+            cids.len(),
+            created
+        );
+        // The same items always produce the same list, regardless of concurrent writes.
+        let mut expected = (0..10_000).collect::<Vec<_>>();
+        expected.splice(5_000..5_001, [0, 1, 2]);
+        let mut created = test_list().with_concurrency(16);
+        for i in expected {
+            created.push(i);
+        }
+        assert_eq!(
+            created.save(&store).await.unwrap(),
+            list.save(&store).await.unwrap()
+        );
+    }
+    #[tokio::test]
+    async fn stream_is_lazy() {
+        let store = Arc::new(CountingStore::default());
+        let mut list = test_list();
+        for i in 0..10_000 {
+            list.push(i);
+        }
+        let mut cids = Vec::new();
+        list.save_with_cids(&store, &mut cids).await.unwrap();
+        list.push(10_000);
+        let first = list
+            .stream(&store, 5_000..)
+            .take(3)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(first, vec![5_000, 5_001, 5_002]);
+        let reads = store.reads.load(Ordering::Relaxed);
+        assert!(
+            reads < cids.len() / 10,
+            "{reads} of {} blocks read",
+            cids.len()
+        );
+        assert_eq!(
+            list.stream(&store, 9_998..)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap(),
+            vec![9_998, 9_999, 10_000]
+        );
+    }
+    #[tokio::test]
+    async fn truncate_matches_create() {
+        let store = Memory::test();
+        let mut list = test_list();
+        for i in 0..2_000 {
+            list.push(i);
+        }
+        let cid = list.save(&store).await.unwrap();
+        for len in (1..2_000).step_by(3) {
+            let mut truncated = ProllyList::<u32>::open(&store, &cid).await.unwrap();
+            truncated.splice(&store, len.., []).await.unwrap();
+            let mut created = test_list();
+            for i in 0..len as u32 {
+                created.push(i);
+            }
+            assert_eq!(
+                truncated.save(&store).await.unwrap(),
+                created.save(&store).await.unwrap(),
+                "len {len}"
+            );
+        }
+    }
+    #[derive(Debug, Clone, test_strategy::Arbitrary)]
+    enum Op {
+        Push(u32),
+        Splice(
+            #[strategy(0..1_000usize)] usize,
+            #[strategy(0..50usize)] usize,
+            #[strategy(vec(0..1_000u32, 0..50))] Vec<u32>,
+        ),
+        Save,
+    }
+    #[proptest]
+    fn model(#[strategy(vec(proptest::arbitrary::any::<Op>(), 0..200))] ops: Vec<Op>) {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async move {
+            let store = Memory::test();
+            let mut list = test_list();
+            let mut model = Vec::new();
+            for op in ops {
+                match op {
+                    Op::Push(item) => {
+                        list.push(item);
+                        model.push(item);
+                    },
+                    Op::Splice(start, len, items) => {
+                        let start = start.min(model.len());
+                        let end = (start + len).min(model.len());
+                        let removed = list
+                            .splice(&store, start..end, items.clone())
+                            .await
+                            .unwrap();
+                        assert_eq!(removed, model.splice(start..end, items).collect::<Vec<_>>());
+                    },
+                    Op::Save => {
+                        let cid = list.save(&store).await.unwrap();
+                        list = ProllyList::open(&store, &cid).await.unwrap();
+                    },
+                }
+            }
+            assert_eq!(list.len(), model.len());
+            assert_eq!(list.to_vec(&store).await.unwrap(), model);
+            let mut created = test_list();
+            for item in model.iter().copied() {
+                created.push(item);
+            }
+            assert_eq!(
+                created.save(&store).await.unwrap(),
+                list.save(&store).await.unwrap(),
+                "the same items always produce the same list"
+            );
+        });
+    }
+}
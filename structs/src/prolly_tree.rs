@@ -1,11 +1,942 @@
-pub mod refimpl {}
-
-// #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-// #[cfg_attr(
-//     feature = "rkyv",
-//     derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)
-// )]
-// #[derive(Debug, Clone, PartialEq, Eq)]
-// pub enum Node<Key, Value, Cid> {
-//
-// }
+pub mod cursor_create;
+pub mod cursor_diff;
+pub mod cursor_read;
+pub mod cursor_update;
+pub mod merge;
+pub mod roller;
+#[cfg(any(test, feature = "debug_checks"))]
+pub mod verify;
+
+use self::{
+    cursor_diff::{CursorDiff, Diff},
+    cursor_read::{CursorRead, DEFAULT_PREFETCH},
+    cursor_update::{Change, CursorUpdate},
+    merge::{ConflictResolver, RejectConflicts},
+    roller::{Config as RollerConfig, Roller},
+};
+use async_trait::async_trait;
+use fixity_store::{
+    container::{ContainerDescription, DescribeContainer, PersistContainer, ReconcileContainer},
+    content_store::ContentStore,
+    contentid::Cid,
+    deser::{Deserialize, Serialize},
+    deser_ext::DeserExt,
+    store::StoreError,
+};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    ops::{Bound, RangeBounds},
+    sync::Arc,
+};
+
+/// A single block of a prolly tree.
+///
+/// Each branch entry holds the first key of the child node, such that the entries of every
+/// level are sorted by key.
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)
+)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node<K, V> {
+    Branch(Vec<(K, Cid)>),
+    Leaf(Vec<(K, V)>),
+}
+impl<K, V> Node<K, V> {
+    /// Len of the underlying vec.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Branch(v) => v.len(),
+            Self::Leaf(v) => v.len(),
+        }
+    }
+    /// Whether or not the underlying vec is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+/// A node of a prolly tree, such that maps indexed by key and lists indexed by position share the
+/// code building and walking their nodes.
+pub(crate) trait TreeNode: Sized {
+    /// An entry of a leaf.
+    type Item;
+    /// What a branch entry records of its child node.
+    type Key;
+    fn leaf(items: Vec<Self::Item>) -> Self;
+    fn branch(entries: Vec<(Self::Key, Cid)>) -> Self;
+    /// The entries of a branch, or `None` for a leaf.
+    fn entries(&self) -> Option<&[(Self::Key, Cid)]>;
+    /// The key of the branch entry pointing to a leaf of the given items.
+    fn leaf_key(items: &[Self::Item]) -> Self::Key;
+    /// The key of the branch entry pointing to a branch of the given entries.
+    fn branch_key(entries: &[(Self::Key, Cid)]) -> Self::Key;
+    /// Roll the bytes of an item or of the Cid of a child, returning whether it ends the node.
+    ///
+    /// Each level has its own roller, reset as each node of the level is written.
+    fn roll(roller: &mut Roller, bytes: &[u8]) -> bool;
+}
+impl<K: Clone, V> TreeNode for Node<K, V> {
+    type Item = (K, V);
+    type Key = K;
+    fn leaf(kvs: Vec<(K, V)>) -> Self {
+        Self::Leaf(kvs)
+    }
+    fn branch(entries: Vec<(K, Cid)>) -> Self {
+        Self::Branch(entries)
+    }
+    fn entries(&self) -> Option<&[(K, Cid)]> {
+        match self {
+            Self::Branch(entries) => Some(entries),
+            Self::Leaf(_) => None,
+        }
+    }
+    fn leaf_key(kvs: &[(K, V)]) -> K {
+        kvs.first().expect("leaf is not empty").0.clone()
+    }
+    fn branch_key(entries: &[(K, Cid)]) -> K {
+        entries.first().expect("branch is not empty").0.clone()
+    }
+    fn roll(roller: &mut Roller, bytes: &[u8]) -> bool {
+        // Keys are unique, so each item is rolled on its own. An update can then resume rolling
+        // from any key without replaying the keys before it.
+        roller.roll_item(bytes)
+    }
+}
+/// The persisted form of a [`ProllyTree`], metadata pointing to the root [`Node`] of the tree.
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)
+)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Root {
+    /// The root node of the tree, or `None` if the tree is empty.
+    pub node: Option<Cid>,
+    /// The roller the nodes of the tree were chunked with, used for every later update.
+    pub roller_config: RollerConfig,
+}
+/// A key where every key starting with a given prefix falls within a single range of keys.
+pub trait PrefixKey: Sized {
+    /// The range of keys starting with `self`.
+    fn prefix_range(&self) -> (Bound<Self>, Bound<Self>);
+}
+impl PrefixKey for Vec<u8> {
+    fn prefix_range(&self) -> (Bound<Self>, Bound<Self>) {
+        // The end is the prefix with the last byte incremented, dropping any bytes that would
+        // overflow.
+        let mut end = self.clone();
+        while let Some(b) = end.pop() {
+            if b < u8::MAX {
+                end.push(b + 1);
+                return (Bound::Included(self.clone()), Bound::Excluded(end));
+            }
+        }
+        (Bound::Included(self.clone()), Bound::Unbounded)
+    }
+}
+impl PrefixKey for String {
+    fn prefix_range(&self) -> (Bound<Self>, Bound<Self>) {
+        // Strings are ordered by their chars, so the end is the prefix with the last char
+        // incremented, dropping any chars that would overflow.
+        let mut end = self.clone();
+        while let Some(c) = end.pop() {
+            let next = (c as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+            if let Some(next) = next {
+                end.push(next);
+                return (Bound::Included(self.clone()), Bound::Excluded(end));
+            }
+        }
+        (Bound::Included(self.clone()), Bound::Unbounded)
+    }
+}
+/// A sorted map stored as a probabilistic B-tree, where node boundaries are defined by the
+/// content of the entries such that the same entries always produce the same tree.
+///
+/// Changes are staged in memory and written on save, rewriting only the nodes that changed.
+#[derive(Debug)]
+pub struct ProllyTree<K, V> {
+    roller_config: RollerConfig,
+    /// The root node as of the last open or save.
+    node: Option<Cid>,
+    /// Changes since the last open or save.
+    changes: BTreeMap<K, Change<V>>,
+    /// The root as of the last open or save, if unchanged since.
+    root: Option<Cid>,
+    /// Resolves conflicting changes when merging.
+    resolver: Arc<dyn ConflictResolver<K, V>>,
+    /// The number of leaf nodes read ahead of a stream.
+    prefetch: usize,
+}
+impl<K, V> ProllyTree<K, V>
+where
+    K: Ord,
+{
+    pub fn new() -> Self {
+        Self::with_roller(RollerConfig::default())
+    }
+    pub fn with_roller(roller_config: RollerConfig) -> Self {
+        Self {
+            roller_config,
+            node: None,
+            changes: BTreeMap::new(),
+            root: None,
+            resolver: Arc::new(RejectConflicts),
+            prefetch: DEFAULT_PREFETCH,
+        }
+    }
+    /// Resolve conflicting changes when merging with the given resolver, rather than failing the
+    /// merge.
+    pub fn with_resolver<R>(mut self, resolver: R) -> Self
+    where
+        R: ConflictResolver<K, V> + 'static,
+    {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+    /// Read up to `prefetch` leaf nodes ahead of a stream, see [`CursorRead::with_prefetch`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefetch` is zero.
+    pub fn with_prefetch(mut self, prefetch: usize) -> Self {
+        assert!(prefetch > 0, "prefetch must read at least one node");
+        self.prefetch = prefetch;
+        self
+    }
+    pub fn insert(&mut self, key: K, value: V) {
+        self.changes.insert(key, Change::Insert(value));
+    }
+    pub fn remove(&mut self, key: K) {
+        self.changes.insert(key, Change::Remove);
+    }
+    /// The keys removed since the last open or save.
+    pub(crate) fn removed(&self) -> impl Iterator<Item = &K> {
+        self.changes
+            .iter()
+            .filter(|(_, change)| matches!(change, Change::Remove))
+            .map(|(k, _)| k)
+    }
+}
+impl<K, V> ProllyTree<K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    pub async fn get<S>(&self, store: &Arc<S>, key: &K) -> Result<Option<V>, StoreError>
+    where
+        S: ContentStore,
+        Node<K, V>: Deserialize,
+    {
+        match self.changes.get(key) {
+            Some(Change::Insert(value)) => Ok(Some(value.clone())),
+            Some(Change::Remove) => Ok(None),
+            None => CursorRead::new(store, self.node).get(key).await,
+        }
+    }
+    /// The entries within the given range of keys, in ascending order.
+    pub async fn range<S, R>(&self, store: &Arc<S>, range: R) -> Result<Vec<(K, V)>, StoreError>
+    where
+        S: ContentStore,
+        R: RangeBounds<K>,
+        Node<K, V>: Deserialize,
+    {
+        let stored = CursorRead::new(store, self.node)
+            .range((
+                clone_bound(range.start_bound()),
+                clone_bound(range.end_bound()),
+            ))
+            .await?;
+        if self.changes.is_empty() {
+            return Ok(stored);
+        }
+        let mut entries = stored.into_iter().collect::<BTreeMap<_, _>>();
+        for (key, change) in self.changes.range(range) {
+            match change {
+                Change::Insert(value) => entries.insert(key.clone(), value.clone()),
+                Change::Remove => entries.remove(key),
+            };
+        }
+        Ok(entries.into_iter().collect())
+    }
+    /// Stream the entries within the given range of keys, in ascending order.
+    ///
+    /// Leaf nodes are read ahead of the stream, such that a long scan is not limited to a single
+    /// read at a time, see [`Self::with_prefetch`].
+    pub fn range_stream<'s, S, R>(
+        &self,
+        store: &'s Arc<S>,
+        range: R,
+    ) -> impl Stream<Item = Result<(K, V), StoreError>> + 's
+    where
+        S: ContentStore,
+        R: RangeBounds<K>,
+        K: Send + 's,
+        V: Send + 's,
+        Node<K, V>: Deserialize,
+    {
+        self.ordered_stream(store, range, false)
+    }
+    /// Like [`Self::range_stream`], but in descending order.
+    pub fn range_stream_rev<'s, S, R>(
+        &self,
+        store: &'s Arc<S>,
+        range: R,
+    ) -> impl Stream<Item = Result<(K, V), StoreError>> + 's
+    where
+        S: ContentStore,
+        R: RangeBounds<K>,
+        K: Send + 's,
+        V: Send + 's,
+        Node<K, V>: Deserialize,
+    {
+        self.ordered_stream(store, range, true)
+    }
+    /// Stream the entries with keys starting with `prefix`, in ascending order.
+    pub fn prefix_stream<'s, S>(
+        &self,
+        store: &'s Arc<S>,
+        prefix: &K,
+    ) -> impl Stream<Item = Result<(K, V), StoreError>> + 's
+    where
+        S: ContentStore,
+        K: PrefixKey + Send + 's,
+        V: Send + 's,
+        Node<K, V>: Deserialize,
+    {
+        self.ordered_stream(store, prefix.prefix_range(), false)
+    }
+    fn ordered_stream<'s, S, R>(
+        &self,
+        store: &'s Arc<S>,
+        range: R,
+        reverse: bool,
+    ) -> impl Stream<Item = Result<(K, V), StoreError>> + 's
+    where
+        S: ContentStore,
+        R: RangeBounds<K>,
+        K: Send + 's,
+        V: Send + 's,
+        Node<K, V>: Deserialize,
+    {
+        let range = (
+            clone_bound(range.start_bound()),
+            clone_bound(range.end_bound()),
+        );
+        let mut changes = self
+            .changes
+            .range(range.clone())
+            .map(|(k, change)| (k.clone(), change.clone()))
+            .collect::<Vec<_>>();
+        if reverse {
+            changes.reverse();
+        }
+        let stored = CursorRead::new(store, self.node)
+            .with_prefetch(self.prefetch)
+            .ordered_stream(range, reverse)
+            .fuse();
+        // Merge the unsaved changes into the stored entries, both in stream order.
+        let state = (Box::pin(stored), None, changes.into_iter().peekable());
+        stream::try_unfold(
+            state,
+            move |(mut stored, mut next, mut changes)| async move {
+                loop {
+                    if next.is_none() {
+                        next = stored.try_next().await?;
+                    }
+                    let ordering = match (&next, changes.peek()) {
+                        (None, None) => return Ok(None),
+                        (Some(_), None) => Ordering::Less,
+                        (None, Some(_)) => Ordering::Greater,
+                        (Some((k, _)), Some((changed, _))) if reverse => changed.cmp(k),
+                        (Some((k, _)), Some((changed, _))) => k.cmp(changed),
+                    };
+                    if ordering == Ordering::Less {
+                        let kv = next.take().expect("next entry checked above");
+                        return Ok(Some((kv, (stored, next, changes))));
+                    }
+                    if ordering == Ordering::Equal {
+                        // The change replaces the stored entry.
+                        next = None;
+                    }
+                    if let Some((k, Change::Insert(v))) = changes.next() {
+                        return Ok(Some(((k, v), (stored, next, changes))));
+                    }
+                }
+            },
+        )
+    }
+    /// Stream the differences from the `old` tree to this tree, in key order.
+    ///
+    /// Both trees are compared as of their last open or save, ignoring unsaved changes.
+    pub fn diff_stream<'s, S>(
+        &self,
+        store: &'s Arc<S>,
+        old: &Self,
+    ) -> impl Stream<Item = Result<Diff<K, V>, StoreError>> + 's
+    where
+        S: ContentStore,
+        K: 's,
+        V: PartialEq + 's,
+        Node<K, V>: Deserialize,
+    {
+        CursorDiff::new(store, old.node, self.node).into_stream()
+    }
+}
+impl<K, V> ProllyTree<K, V>
+where
+    K: Ord + Clone + Send + Sync,
+    V: Clone + PartialEq + Send + Sync,
+    Node<K, V>: Serialize + Deserialize,
+    (K, V): Serialize,
+{
+    /// Merge the changes made by `other` since the `ancestor` root into this tree, where keys
+    /// changed differently by both sides are passed to the `resolver`.
+    ///
+    /// Only the keys `other` changed are compared, skipping the subtrees it shares with the
+    /// ancestor. The merged changes are staged like any other change. Without an `ancestor`, every
+    /// key of `other` is compared.
+    pub async fn merge_from<S>(
+        &mut self,
+        store: &Arc<S>,
+        ancestor: Option<&Cid>,
+        other: &Cid,
+        resolver: &dyn ConflictResolver<K, V>,
+    ) -> Result<(), StoreError>
+    where
+        S: ContentStore,
+    {
+        // Nothing to merge if `other` is already part of this tree.
+        if ancestor == Some(other) {
+            return Ok(());
+        }
+        let theirs = store.get_owned_unchecked::<Root>(other).await?;
+        if theirs.node == self.node {
+            return Ok(());
+        }
+        let ancestor = match ancestor {
+            Some(ancestor) => store.get_owned_unchecked::<Root>(ancestor).await?.node,
+            None => None,
+        };
+        // Fast forward if this tree is unchanged since the ancestor, such that both become the
+        // same root.
+        if self.changes.is_empty() && self.node == ancestor {
+            self.node = theirs.node;
+            self.roller_config = theirs.roller_config;
+            self.root = Some(*other);
+            return Ok(());
+        }
+        let mut cursor = CursorDiff::new(store, ancestor, theirs.node);
+        while let Some(diff) = cursor.next().await? {
+            let (k, ancestor, theirs) = match diff {
+                Diff::Added(k, v) => (k, None, Some(v)),
+                Diff::Removed(k, v) => (k, Some(v), None),
+                Diff::Changed(k, old, new) => (k, Some(old), Some(new)),
+            };
+            let ours = self.get(store, &k).await?;
+            let merged = if ours == ancestor {
+                theirs
+            } else if ours == theirs {
+                continue;
+            } else {
+                resolver.resolve(&k, ancestor.as_ref(), ours.as_ref(), theirs.as_ref())?
+            };
+            match merged {
+                Some(v) => self.insert(k, v),
+                None => self.remove(k),
+            }
+        }
+        Ok(())
+    }
+}
+fn clone_bound<K: Clone>(bound: Bound<&K>) -> Bound<K> {
+    match bound {
+        Bound::Included(k) => Bound::Included(k.clone()),
+        Bound::Excluded(k) => Bound::Excluded(k.clone()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+impl<K: Ord, V> Default for ProllyTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K, V> DescribeContainer for ProllyTree<K, V> {
+    fn description() -> ContainerDescription {
+        // TODO: Describe `K` and `V`, once params are supported.
+        ContainerDescription {
+            name: "ProllyTree",
+            params: Default::default(),
+        }
+    }
+}
+#[async_trait]
+impl<K, V, S> PersistContainer<S> for ProllyTree<K, V>
+where
+    S: ContentStore,
+    K: Ord + Clone + Send + Sync,
+    V: Send + Sync,
+    Node<K, V>: Serialize + Deserialize,
+    (K, V): Serialize,
+{
+    async fn open(store: &Arc<S>, cid: &Cid) -> Result<Self, StoreError> {
+        let Root {
+            node,
+            roller_config,
+        } = store.get_owned_unchecked::<Root>(cid).await?;
+        Ok(Self {
+            node,
+            root: Some(*cid),
+            ..Self::with_roller(roller_config)
+        })
+    }
+    async fn save(&mut self, store: &Arc<S>) -> Result<Cid, StoreError> {
+        let mut cids = Vec::new();
+        self.save_with_cids(store, &mut cids).await?;
+        Ok(cids.pop().expect("root cid written"))
+    }
+    async fn save_with_cids(
+        &mut self,
+        store: &Arc<S>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<(), StoreError> {
+        // Without changes since the last open or save, the stored root is already current.
+        if let (Some(root), true) = (self.root, self.changes.is_empty()) {
+            cids_buf.push(root);
+            return Ok(());
+        }
+        let changes = std::mem::take(&mut self.changes);
+        self.node = CursorUpdate::with_roller(store, self.node, self.roller_config)
+            .apply(changes, cids_buf)
+            .await?;
+        #[cfg(feature = "debug_checks")]
+        verify::verify::<S, K, V>(store, self.node, self.roller_config)
+            .await
+            .expect("saved prolly tree is well formed");
+        let root = Root {
+            node: self.node,
+            roller_config: self.roller_config,
+        };
+        store.put_with_cids(&root, cids_buf).await?;
+        self.root = Some(*cids_buf.last().expect("root cid written"));
+        Ok(())
+    }
+}
+#[async_trait]
+impl<K, V, S> ReconcileContainer<S> for ProllyTree<K, V>
+where
+    S: ContentStore,
+    K: Ord + Clone + Send + Sync,
+    V: Clone + PartialEq + Send + Sync,
+    Node<K, V>: Serialize + Deserialize,
+    (K, V): Serialize,
+{
+    /// A merge of `other` into this tree without a common ancestor, where every key of `other`
+    /// is compared and keys differing from this tree are passed to the resolver.
+    ///
+    /// Roots do not record the history of the tree, so callers tracking the common ancestor of
+    /// both sides should use [`ProllyTree::merge_from`] instead.
+    async fn merge(&mut self, store: &Arc<S>, other: &Cid) -> Result<(), StoreError> {
+        let resolver = Arc::clone(&self.resolver);
+        self.merge_from(store, None, other, resolver.as_ref()).await
+    }
+    /// The entries of this tree added or changed since `other`.
+    ///
+    /// Entries removed since `other` are not represented, as the tree has no tombstones.
+    async fn diff(&mut self, store: &Arc<S>, other: &Cid) -> Result<Self, StoreError> {
+        let other = Self::open(store, other).await?;
+        let mut diff = Self::with_roller(self.roller_config);
+        let mut cursor = CursorDiff::new(store, other.node, self.node);
+        while let Some(change) = cursor.next().await? {
+            match change {
+                Diff::Added(k, v) | Diff::Changed(k, _, v) => diff.insert(k, v),
+                Diff::Removed(..) => {},
+            }
+        }
+        // Unsaved changes take precedence over the stored entries they replace.
+        for (k, change) in self.changes.iter() {
+            match change {
+                Change::Insert(v) if other.get(store, k).await?.as_ref() != Some(v) => {
+                    diff.insert(k.clone(), v.clone())
+                },
+                _ => {
+                    diff.changes.remove(k);
+                },
+            }
+        }
+        Ok(diff)
+    }
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use fixity_store::stores::memory::Memory;
+    use futures::TryStreamExt;
+    use proptest::collection::vec;
+    use test_strategy::proptest;
+
+    /// A smaller value to use with the roller, producing smaller average block sizes.
+    pub(crate) const TEST_PATTERN: u32 = (1 << 8) - 1;
+
+    #[tokio::test]
+    async fn poc() {
+        let store = Memory::test();
+        let mut tree = ProllyTree::with_roller(RollerConfig::with_pattern(TEST_PATTERN));
+        for i in 0..100u32 {
+            tree.insert(i, i * 10);
+        }
+        assert_eq!(tree.get(&store, &5).await.unwrap(), Some(50));
+        let cid = tree.save(&store).await.unwrap();
+        assert_eq!(tree.get(&store, &5).await.unwrap(), Some(50));
+        tree.remove(5);
+        tree.insert(200, 2_000);
+        assert_eq!(tree.get(&store, &5).await.unwrap(), None);
+        assert_eq!(
+            tree.range(&store, 3..7).await.unwrap(),
+            vec![(3, 30), (4, 40), (6, 60)]
+        );
+        tree.save(&store).await.unwrap();
+        assert_eq!(
+            tree.range(&store, 98..).await.unwrap(),
+            vec![(98, 980), (99, 990), (200, 2_000)]
+        );
+        let reopened = ProllyTree::<u32, u32>::open(&store, &cid).await.unwrap();
+        assert_eq!(reopened.get(&store, &5).await.unwrap(), Some(50));
+        assert_eq!(reopened.range(&store, ..).await.unwrap().len(), 100);
+    }
+    #[tokio::test]
+    async fn unchanged_nodes_not_rewritten() {
+        let store = Memory::test();
+        let mut tree = ProllyTree::with_roller(RollerConfig::with_pattern(TEST_PATTERN));
+        for i in 0..10_000u32 {
+            tree.insert(i, i);
+        }
+        let mut cids = Vec::new();
+        tree.save_with_cids(&store, &mut cids).await.unwrap();
+        let created = cids.len();
+        tree.insert(5_000, 0);
+        let mut cids = Vec::new();
+        tree.save_with_cids(&store, &mut cids).await.unwrap();
+        assert!(
+            cids.len() < created / 50,
+            "{} of {} blocks written",
+            cids.len(),
+            created
+        );
+    }
+    #[tokio::test]
+    async fn persists_roller() {
+        let store = Memory::test();
+        let mut created = ProllyTree::with_roller(RollerConfig::with_pattern(TEST_PATTERN));
+        for i in 0..1_000u32 {
+            created.insert(i, i);
+        }
+        let cid = created.save(&store).await.unwrap();
+        created.insert(5_000, 0);
+        created.save(&store).await.unwrap();
+        // Updates of an opened tree find the same boundaries the tree was created with.
+        let mut reopened = ProllyTree::<u32, u32>::open(&store, &cid).await.unwrap();
+        reopened.insert(5_000, 0);
+        reopened.save(&store).await.unwrap();
+        assert_eq!(reopened.node, created.node);
+        // As do updates of a tree fast forwarded to it.
+        let mut merged = ProllyTree::<u32, u32>::new();
+        merged.merge(&store, &cid).await.unwrap();
+        merged.insert(5_000, 0);
+        merged.save(&store).await.unwrap();
+        assert_eq!(merged.node, created.node);
+    }
+    #[tokio::test]
+    async fn diff() {
+        let store = Memory::test();
+        let mut old = ProllyTree::with_roller(RollerConfig::with_pattern(TEST_PATTERN));
+        for i in 0..1_000u32 {
+            old.insert(i, i);
+        }
+        let old_cid = old.save(&store).await.unwrap();
+        let mut new = ProllyTree::<u32, u32>::open(&store, &old_cid)
+            .await
+            .unwrap();
+        new.insert(10, 0);
+        new.remove(20);
+        new.insert(2_000, 1);
+        new.save(&store).await.unwrap();
+        let changes = new
+            .diff_stream(&store, &old)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                Diff::Changed(10, 10, 0),
+                Diff::Removed(20, 20),
+                Diff::Added(2_000, 1)
+            ]
+        );
+        // Unsaved changes are included in the diff as well.
+        new.insert(30, 0);
+        new.insert(40, 40);
+        new.insert(2_000, 2_000);
+        let diff = new.diff(&store, &old_cid).await.unwrap();
+        assert_eq!(
+            diff.range(&store, ..).await.unwrap(),
+            vec![(10, 0), (30, 0), (2_000, 2_000)]
+        );
+        let mut old = ProllyTree::<u32, u32>::open(&store, &old_cid)
+            .await
+            .unwrap();
+        let diff = old.diff(&store, &old_cid).await.unwrap();
+        assert_eq!(diff.range(&store, ..).await.unwrap(), vec![]);
+    }
+    #[tokio::test]
+    async fn streams() {
+        let store = Memory::test();
+        let mut tree = ProllyTree::with_roller(RollerConfig::with_pattern(TEST_PATTERN));
+        for i in 0..1_000u32 {
+            tree.insert(i, i);
+        }
+        tree.save(&store).await.unwrap();
+        tree.remove(10);
+        tree.insert(11, 0);
+        tree.insert(2_000, 0);
+        assert_eq!(
+            tree.range_stream(&store, 9..13)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap(),
+            vec![(9, 9), (11, 0), (12, 12)]
+        );
+        assert_eq!(
+            tree.range_stream_rev(&store, 998..)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap(),
+            vec![(2_000, 0), (999, 999), (998, 998)]
+        );
+        let mut tree = ProllyTree::with_roller(RollerConfig::with_pattern(TEST_PATTERN));
+        for key in ["a", "ab", "abc", "ab\u{10FFFF}", "ac", "b"] {
+            tree.insert(key.to_owned(), ());
+        }
+        for i in 0..1_000 {
+            tree.insert(format!("z{}", i), ());
+        }
+        tree.save(&store).await.unwrap();
+        let keys = tree
+            .prefix_stream(&store, &"ab".to_owned())
+            .map_ok(|(k, ())| k)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(keys, vec!["ab", "abc", "ab\u{10FFFF}"]);
+        assert_eq!(
+            "a\u{10FFFF}".to_owned().prefix_range().1,
+            Bound::Excluded("b".to_owned())
+        );
+        assert_eq!(vec![1, 255].prefix_range().1, Bound::Excluded(vec![2]));
+        assert_eq!(vec![255].prefix_range().1, Bound::Unbounded);
+    }
+    #[derive(Debug, Clone, test_strategy::Arbitrary)]
+    enum Op {
+        Insert(#[strategy(0..500u16)] u16, u16),
+        Remove(#[strategy(0..500u16)] u16),
+        Save,
+    }
+    #[proptest]
+    fn model(#[strategy(vec(proptest::arbitrary::any::<Op>(), 0..300))] ops: Vec<Op>) {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async move {
+            let store = Memory::test();
+            let mut tree = ProllyTree::with_roller(RollerConfig::with_pattern(TEST_PATTERN));
+            let mut model = BTreeMap::new();
+            for op in ops {
+                match op {
+                    Op::Insert(k, v) => {
+                        tree.insert(k, v);
+                        model.insert(k, v);
+                    },
+                    Op::Remove(k) => {
+                        tree.remove(k);
+                        model.remove(&k);
+                    },
+                    Op::Save => {
+                        let cid = tree.save(&store).await.unwrap();
+                        tree = ProllyTree::open(&store, &cid).await.unwrap();
+                    },
+                }
+            }
+            let expected = model.into_iter().collect::<Vec<_>>();
+            assert_eq!(tree.range(&store, ..).await.unwrap(), expected);
+            let streamed = tree.range_stream(&store, ..).try_collect::<Vec<_>>();
+            assert_eq!(streamed.await.unwrap(), expected);
+            let mut streamed = tree
+                .range_stream_rev(&store, ..)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            streamed.reverse();
+            assert_eq!(streamed, expected);
+            tree.save(&store).await.unwrap();
+            assert_eq!(tree.range(&store, ..).await.unwrap(), expected);
+            for (k, v) in expected {
+                assert_eq!(tree.get(&store, &k).await.unwrap(), Some(v));
+            }
+        });
+    }
+    /// Resolve conflicts with the greater value, where a removed key is the least value.
+    fn max_wins(
+        _: &u16,
+        _: Option<&u16>,
+        ours: Option<&u16>,
+        theirs: Option<&u16>,
+    ) -> Result<Option<u16>, StoreError> {
+        Ok(ours.max(theirs).copied())
+    }
+    async fn open_test(store: &Arc<Memory>, cid: &Cid) -> ProllyTree<u16, u16> {
+        ProllyTree::open(store, cid)
+            .await
+            .unwrap()
+            .with_resolver(max_wins)
+    }
+    #[tokio::test]
+    async fn merge() {
+        let store = Memory::test();
+        let mut base = ProllyTree::with_roller(RollerConfig::with_pattern(TEST_PATTERN));
+        for i in 0..1_000u16 {
+            base.insert(i, i);
+        }
+        let base = base.save(&store).await.unwrap();
+        let mut ours = open_test(&store, &base).await;
+        ours.insert(1, 0);
+        ours.remove(2);
+        ours.insert(5, 50);
+        ours.save(&store).await.unwrap();
+        let mut theirs = open_test(&store, &base).await;
+        theirs.insert(1, 0);
+        theirs.insert(3, 30);
+        theirs.remove(4);
+        theirs.insert(5, 51);
+        theirs.insert(2_000, 1);
+        let theirs_cid = theirs.save(&store).await.unwrap();
+        ours.merge_from(&store, Some(&base), &theirs_cid, &max_wins)
+            .await
+            .unwrap();
+        let ours_cid = ours.save(&store).await.unwrap();
+        let mut expected = (0..1_000u16).map(|i| (i, i)).collect::<BTreeMap<_, _>>();
+        expected.extend([(1, 0), (3, 30), (5, 51), (2_000, 1)]);
+        expected.remove(&2);
+        expected.remove(&4);
+        assert_eq!(
+            ours.range(&store, ..).await.unwrap(),
+            expected.into_iter().collect::<Vec<_>>()
+        );
+        // Merging back fast forwards to the same root.
+        theirs
+            .merge_from(&store, Some(&theirs_cid), &ours_cid, &max_wins)
+            .await
+            .unwrap();
+        assert_eq!(theirs.save(&store).await.unwrap(), ours_cid);
+        // Merging a root already merged changes nothing.
+        ours.merge_from(&store, Some(&base), &theirs_cid, &max_wins)
+            .await
+            .unwrap();
+        assert_eq!(ours.save(&store).await.unwrap(), ours_cid);
+        // Without an ancestor, keys only in this tree are kept and keys differing are resolved.
+        let mut unrelated = ProllyTree::with_roller(RollerConfig::with_pattern(TEST_PATTERN))
+            .with_resolver(max_wins);
+        unrelated.insert(4, 40);
+        unrelated.insert(5, 0);
+        unrelated.insert(3_000, 0);
+        unrelated.merge(&store, &ours_cid).await.unwrap();
+        let mut expected = ours
+            .range(&store, ..)
+            .await
+            .unwrap()
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        expected.extend([(4, 40), (3_000, 0)]);
+        assert_eq!(
+            unrelated.range(&store, ..).await.unwrap(),
+            expected.into_iter().collect::<Vec<_>>()
+        );
+        // Conflicts fail the merge without a resolver.
+        let mut ours = ProllyTree::<u16, u16>::open(&store, &base).await.unwrap();
+        ours.insert(5, 52);
+        assert!(matches!(
+            ours.merge(&store, &theirs_cid).await,
+            Err(StoreError::UnmergableType)
+        ));
+    }
+    #[proptest]
+    fn merge_model(
+        #[strategy(vec(proptest::arbitrary::any::<Op>(), 0..100))] base_ops: Vec<Op>,
+        #[strategy(vec(proptest::arbitrary::any::<Op>(), 0..100))] our_ops: Vec<Op>,
+        #[strategy(vec(proptest::arbitrary::any::<Op>(), 0..100))] their_ops: Vec<Op>,
+    ) {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async move {
+            let store = Memory::test();
+            /// Apply the ops to both the tree and the model, saving the tree on every save op.
+            async fn apply(
+                store: &Arc<Memory>,
+                tree: &mut ProllyTree<u16, u16>,
+                model: &mut BTreeMap<u16, u16>,
+                ops: Vec<Op>,
+            ) -> Cid {
+                for op in ops {
+                    match op {
+                        Op::Insert(k, v) => {
+                            tree.insert(k, v);
+                            model.insert(k, v);
+                        },
+                        Op::Remove(k) => {
+                            tree.remove(k);
+                            model.remove(&k);
+                        },
+                        Op::Save => {
+                            tree.save(store).await.unwrap();
+                        },
+                    }
+                }
+                tree.save(store).await.unwrap()
+            }
+            let mut base = ProllyTree::with_roller(RollerConfig::with_pattern(TEST_PATTERN));
+            let mut base_model = BTreeMap::new();
+            let base = apply(&store, &mut base, &mut base_model, base_ops).await;
+            let (mut ours, mut our_model) = (open_test(&store, &base).await, base_model.clone());
+            apply(&store, &mut ours, &mut our_model, our_ops).await;
+            let (mut theirs, mut their_model) =
+                (open_test(&store, &base).await, base_model.clone());
+            let theirs = apply(&store, &mut theirs, &mut their_model, their_ops).await;
+            ours.merge_from(&store, Some(&base), &theirs, &max_wins)
+                .await
+                .unwrap();
+            let mut keys = our_model
+                .keys()
+                .chain(their_model.keys())
+                .collect::<Vec<_>>();
+            keys.sort_unstable();
+            keys.dedup();
+            let expected = keys
+                .into_iter()
+                .filter_map(|k| {
+                    let (base, ours, theirs) =
+                        (base_model.get(k), our_model.get(k), their_model.get(k));
+                    let merged = if ours == base {
+                        theirs
+                    } else if theirs == base || ours == theirs {
+                        ours
+                    } else {
+                        ours.max(theirs)
+                    };
+                    merged.map(|v| (*k, *v))
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(ours.range(&store, ..).await.unwrap(), expected);
+        });
+    }
+}
@@ -0,0 +1,478 @@
+use super::{
+    roller::{Config as RollerConfig, Roller},
+    Node, TreeNode,
+};
+use fixity_store::{
+    content_store::ContentStore,
+    contentid::{Cid, ContentId},
+    deser::{Deserialize, Serialize},
+    deser_ext::DeserExt,
+    store::StoreError,
+};
+use futures::{
+    future::BoxFuture,
+    stream::{FuturesUnordered, Stream, TryStreamExt},
+};
+use std::{collections::BTreeMap, mem, sync::Arc};
+
+/// Create a prolly tree with a cursor, optimized for and requiring sorted insertions.
+pub struct CursorCreate<'s, S, K: Clone, V> {
+    builder: Builder<'s, S, Node<K, V>>,
+}
+impl<'s, S, K: Clone, V> CursorCreate<'s, S, K, V> {
+    pub fn new(store: &'s Arc<S>) -> Self {
+        Self::with_roller(store, RollerConfig::default())
+    }
+    pub fn with_roller(store: &'s Arc<S>, roller_config: RollerConfig) -> Self {
+        Self {
+            builder: Builder::new(store, roller_config),
+        }
+    }
+    /// Write up to `writes` nodes to the store concurrently, rather than one at a time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `writes` is zero.
+    pub fn with_concurrency(mut self, writes: usize) -> Self {
+        assert!(writes > 0, "concurrency must allow at least one write");
+        self.builder.concurrency = writes;
+        self
+    }
+    /// Record the Cid of every node written, for [`Self::finish_with_cids`].
+    ///
+    /// Without it only the unfinished node of each level is held in memory, the Cids of written
+    /// nodes are not.
+    pub fn with_cids(mut self) -> Self {
+        self.builder.record_cids();
+        self
+    }
+}
+impl<'s, S, K, V> CursorCreate<'s, S, K, V>
+where
+    S: ContentStore,
+    K: Ord + Clone + Send + Sync,
+    V: Send + Sync,
+    Node<K, V>: Serialize + Deserialize,
+    (K, V): Serialize,
+{
+    /// Create the tree from unsorted key values, where the last value of a duplicate key wins.
+    pub async fn with_kvs(mut self, kvs: Vec<(K, V)>) -> Result<Option<Cid>, StoreError> {
+        let kvs = kvs.into_iter().collect::<BTreeMap<_, _>>();
+        for (k, v) in kvs {
+            self.push(k, v).await?;
+        }
+        self.finish().await
+    }
+    /// Create the tree from a stream of key values in ascending order, without duplicate keys.
+    ///
+    /// The tree is built in a single pass, holding only the unfinished node of each level in
+    /// memory, such that the input can be far larger than memory.
+    pub async fn with_stream<St>(mut self, kvs: St) -> Result<Option<Cid>, StoreError>
+    where
+        St: Stream<Item = Result<(K, V), StoreError>> + Send,
+    {
+        futures::pin_mut!(kvs);
+        let mut last = None;
+        while let Some((k, v)) = kvs.try_next().await? {
+            if matches!(&last, Some(last) if *last >= k) {
+                return Err(StoreError::UnsortedKeys);
+            }
+            last = Some(k.clone());
+            self.push(k, v).await?;
+        }
+        self.finish().await
+    }
+    /// Push the next key value into the tree.
+    ///
+    /// Keys must be pushed in ascending order.
+    pub async fn push(&mut self, k: K, v: V) -> Result<(), StoreError> {
+        self.builder.push((k, v)).await
+    }
+    /// Write the remaining nodes, returning the root node of the tree or `None` if the tree is
+    /// empty.
+    pub async fn finish(self) -> Result<Option<Cid>, StoreError> {
+        self.builder.finish(&mut Vec::new()).await
+    }
+    /// Like [`Self::finish`], but also reporting the Cid of every node written.
+    ///
+    /// Nodes are only recorded with [`Self::with_cids`], otherwise nothing is reported.
+    pub async fn finish_with_cids(
+        self,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<Option<Cid>, StoreError> {
+        self.builder.finish(cids_buf).await
+    }
+}
+/// Builds every level of a prolly tree from items and existing nodes pushed in order, writing
+/// each node once a boundary is found.
+pub(crate) struct Builder<'s, S, N: TreeNode> {
+    store: &'s Arc<S>,
+    roller_config: RollerConfig,
+    leaf: (Roller, Vec<N::Item>),
+    /// The entries of each level of branches, where `branches[0]` holds the entries pointing to
+    /// leaves.
+    branches: Vec<Level<N::Key>>,
+    /// The first node of each level of branches.
+    heads: Vec<Head>,
+    /// Every node written so far, if recorded.
+    written: Option<Vec<Cid>>,
+    /// The last existing node pushed, which may be a branch of a single child.
+    reused: Option<Cid>,
+    /// Node writes not yet finished.
+    writes: FuturesUnordered<BoxFuture<'s, Result<(), StoreError>>>,
+    /// The most node writes to have in flight at once.
+    concurrency: usize,
+}
+/// The unfinished node of a level of branches, and the roller finding its end.
+type Level<K> = (Roller, Vec<(K, Cid)>);
+/// The first node of a level of branches.
+///
+/// Levels are only started as boundaries are found, so the first node of a level may have a single
+/// child. If no other node follows on its level, the node is collapsed out of the tree by
+/// [`Builder::finish`], so it is held until that is known rather than written.
+enum Head {
+    /// The level has no nodes yet.
+    Empty,
+    /// The first node of the level, with a single child, not yet written.
+    Held { cid: Cid, child: Cid, buf: Vec<u8> },
+    /// The first node of the level is written.
+    Written,
+}
+impl<'s, S, N: TreeNode> Builder<'s, S, N> {
+    pub fn new(store: &'s Arc<S>, roller_config: RollerConfig) -> Self {
+        Self {
+            store,
+            roller_config,
+            leaf: (Roller::with_config(roller_config), Vec::new()),
+            branches: Vec::new(),
+            heads: Vec::new(),
+            written: None,
+            reused: None,
+            writes: FuturesUnordered::new(),
+            concurrency: 1,
+        }
+    }
+    /// Write up to `writes` nodes to the store concurrently, rather than one at a time.
+    pub fn with_concurrency(mut self, writes: usize) -> Self {
+        self.concurrency = writes;
+        self
+    }
+    /// Record the Cid of every node written, to be reported by [`Self::finish`].
+    pub fn record_cids(&mut self) {
+        self.written.get_or_insert_with(Vec::new);
+    }
+    /// Whether an existing node of the given height, where leaves are height `0`, can be pushed
+    /// as a whole, aka every level at or below the node is between nodes.
+    pub fn is_aligned(&self, height: usize) -> bool {
+        self.leaf.1.is_empty()
+            && self
+                .branches
+                .iter()
+                .take(height)
+                .all(|(_, entries)| entries.is_empty())
+    }
+}
+impl<'s, S, N> Builder<'s, S, N>
+where
+    S: ContentStore,
+    N: TreeNode + Serialize + Deserialize,
+    N::Item: Serialize,
+{
+    pub async fn push(&mut self, item: N::Item) -> Result<(), StoreError> {
+        let (roller, leaf) = &mut self.leaf;
+        leaf.push(item);
+        let item = leaf.last().expect("item pushed");
+        if N::roll(roller, item.serialize()?.as_ref()) {
+            let entry = self.write_leaf().await?;
+            self.push_entry(0, entry).await?;
+        }
+        Ok(())
+    }
+    /// Push an existing node of the given height, reusing it and every node below it.
+    ///
+    /// The builder must be [aligned](Self::is_aligned) for the node.
+    pub async fn push_node(
+        &mut self,
+        height: usize,
+        key: N::Key,
+        cid: Cid,
+    ) -> Result<(), StoreError> {
+        debug_assert!(self.is_aligned(height));
+        self.reused = Some(cid);
+        self.push_entry(height, (key, cid)).await
+    }
+    async fn push_entry(&mut self, level: usize, entry: (N::Key, Cid)) -> Result<(), StoreError> {
+        let (mut level, mut entry) = (level, entry);
+        loop {
+            while self.branches.len() <= level {
+                self.branches
+                    .push((Roller::with_config(self.roller_config), Vec::new()));
+                self.heads.push(Head::Empty);
+            }
+            // Only the child is rolled. The key of a tree node is also the key of every node on
+            // its leftmost path, so rolling the key would make a boundary key a boundary at every
+            // level.
+            let (roller, entries) = &mut self.branches[level];
+            let boundary = N::roll(roller, entry.1.as_ref());
+            entries.push(entry);
+            if !boundary {
+                return Ok(());
+            }
+            entry = self.write_branch(level).await?;
+            level += 1;
+        }
+    }
+    async fn write_leaf(&mut self) -> Result<(N::Key, Cid), StoreError> {
+        let (roller, leaf) = &mut self.leaf;
+        *roller = Roller::with_config(self.roller_config);
+        let items = mem::take(leaf);
+        let key = N::leaf_key(&items);
+        let (cid, buf) = encode(&N::leaf(items))?;
+        self.write(cid, buf).await?;
+        Ok((key, cid))
+    }
+    async fn write_branch(&mut self, level: usize) -> Result<(N::Key, Cid), StoreError> {
+        let (roller, entries) = &mut self.branches[level];
+        *roller = Roller::with_config(self.roller_config);
+        let entries = mem::take(entries);
+        let key = N::branch_key(&entries);
+        let child = match entries.as_slice() {
+            [(_, child)] => Some(*child),
+            _ => None,
+        };
+        let (cid, buf) = encode(&N::branch(entries))?;
+        match (mem::replace(&mut self.heads[level], Head::Written), child) {
+            (Head::Empty, Some(child)) => self.heads[level] = Head::Held { cid, child, buf },
+            (
+                Head::Held {
+                    cid: held,
+                    buf: held_buf,
+                    ..
+                },
+                _,
+            ) => {
+                self.write(held, held_buf).await?;
+                self.write(cid, buf).await?;
+            },
+            _ => self.write(cid, buf).await?,
+        }
+        Ok((key, cid))
+    }
+    /// Start writing the node, waiting for earlier writes to finish if too many are in flight.
+    async fn write(&mut self, cid: Cid, buf: Vec<u8>) -> Result<(), StoreError> {
+        let store = self.store;
+        self.writes.push(Box::pin(async move {
+            store.write_unchecked(&cid, buf).await?;
+            Ok(())
+        }));
+        while self.writes.len() >= self.concurrency {
+            self.writes.try_next().await?;
+        }
+        if let Some(written) = self.written.as_mut() {
+            written.push(cid);
+        }
+        Ok(())
+    }
+    /// Write the partial node of every level, returning the root node of the tree.
+    ///
+    /// The Cid of every node written is appended to `cids_buf`, if [recorded](Self::record_cids).
+    pub async fn finish(mut self, cids_buf: &mut Vec<Cid>) -> Result<Option<Cid>, StoreError> {
+        let mut carry = if self.leaf.1.is_empty() {
+            None
+        } else {
+            Some(self.write_leaf().await?)
+        };
+        let mut root = carry.as_ref().map(|(_, cid)| *cid);
+        // The level the root is a child of, if the root is not written by this loop.
+        let mut collapsed = None;
+        for level in 0..self.branches.len() {
+            // The last entry of a level does not need to be rolled, it ends the node either way.
+            self.branches[level].1.extend(carry.take());
+            let entries = &self.branches[level].1;
+            if entries.is_empty() {
+                continue;
+            }
+            // A single entry with nothing above it would only be wrapped in nodes of a single
+            // child. The same entries always produce the same tree, so the root is the first node
+            // with more than one child.
+            if entries.len() == 1
+                && self.branches[level + 1..]
+                    .iter()
+                    .all(|(_, entries)| entries.is_empty())
+            {
+                root = Some(entries[0].1);
+                collapsed = Some(level);
+                break;
+            }
+            let entry = self.write_branch(level).await?;
+            root = Some(entry.1);
+            carry = Some(entry);
+        }
+        // The root may in turn be the held first node of a lower level, with a single child.
+        for level in (0..collapsed.unwrap_or(0)).rev() {
+            match &self.heads[level] {
+                Head::Held { cid, child, .. } if Some(*cid) == root => {
+                    root = Some(*child);
+                    self.heads[level] = Head::Empty;
+                },
+                _ => break,
+            }
+        }
+        for head in mem::take(&mut self.heads) {
+            if let Head::Held { cid, buf, .. } = head {
+                self.write(cid, buf).await?;
+            }
+        }
+        while self.writes.try_next().await?.is_some() {}
+        // An existing node only becomes the root if nothing else is left beside it, and it may
+        // itself be a branch of a single child. Those are only read, so no node is orphaned.
+        if root.is_some() && root == self.reused {
+            while let Some(cid) = root {
+                match self.store.get_owned_unchecked::<N>(&cid).await?.entries() {
+                    Some([(_, child)]) => root = Some(*child),
+                    _ => break,
+                }
+            }
+        }
+        cids_buf.extend(self.written.take().into_iter().flatten());
+        Ok(root)
+    }
+}
+/// Serialize the node, returning the Cid of the node with the bytes to write.
+fn encode<T: Serialize>(node: &T) -> Result<(Cid, Vec<u8>), StoreError> {
+    let buf: Vec<u8> = node.serialize()?.into();
+    Ok((<Cid as ContentId>::hash(buf.as_ref()), buf))
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::prolly_tree::{cursor_read::CursorRead, test::TEST_PATTERN, verify::verify};
+    use fixity_store::{content_store::ContentStoreError, stores::memory::Memory};
+    use futures::stream;
+    use std::collections::HashSet;
+
+    /// A store failing every write.
+    #[derive(Debug, Default)]
+    struct ReadOnlyStore(Memory);
+    #[async_trait::async_trait]
+    impl ContentStore for ReadOnlyStore {
+        type Bytes = Arc<[u8]>;
+        async fn exists(&self, cid: &Cid) -> Result<bool, ContentStoreError> {
+            self.0.exists(cid).await
+        }
+        async fn read_unchecked(&self, cid: &Cid) -> Result<Self::Bytes, ContentStoreError> {
+            self.0.read_unchecked(cid).await
+        }
+        async fn write_unchecked<B>(&self, _: &Cid, _: B) -> Result<(), ContentStoreError>
+        where
+            B: AsRef<[u8]> + Into<Arc<[u8]>> + Send,
+        {
+            Err(ContentStoreError::InvalidInput {
+                message: String::from("read only"),
+            })
+        }
+    }
+    /// Every node of the tree with the given root.
+    async fn nodes(store: &Arc<Memory>, root: Option<Cid>) -> HashSet<Cid> {
+        let mut nodes = HashSet::new();
+        let mut stack = root.into_iter().collect::<Vec<_>>();
+        while let Some(cid) = stack.pop() {
+            nodes.insert(cid);
+            if let Node::Branch(entries) = store
+                .get_owned_unchecked::<Node<u32, u32>>(&cid)
+                .await
+                .unwrap()
+            {
+                stack.extend(entries.into_iter().map(|(_, cid)| cid));
+            }
+        }
+        nodes
+    }
+    #[tokio::test]
+    async fn poc() {
+        let contents = vec![(0..20), (0..200), (0..2_000)];
+        for content in contents {
+            let store = Memory::test();
+            let content = content.map(|i| (i, i * 10)).collect::<Vec<(u32, u32)>>();
+            let tree = CursorCreate::with_roller(&store, RollerConfig::with_pattern(TEST_PATTERN));
+            let root = tree.with_kvs(content.clone()).await.unwrap();
+            assert!(root.is_some());
+            let mut reversed = content;
+            reversed.reverse();
+            let tree = CursorCreate::with_roller(&store, RollerConfig::with_pattern(TEST_PATTERN));
+            assert_eq!(tree.with_kvs(reversed).await.unwrap(), root);
+        }
+        let store = Memory::test();
+        let tree = CursorCreate::<_, u32, u32>::new(&store);
+        assert_eq!(tree.with_kvs(Vec::new()).await.unwrap(), None);
+    }
+    #[tokio::test]
+    async fn stream() {
+        let store = Memory::test();
+        let config = RollerConfig::with_pattern(TEST_PATTERN);
+        let kvs = (0..20_000u32).map(|i| (i, i * 10)).collect::<Vec<_>>();
+        let root = CursorCreate::with_roller(&store, config)
+            .with_kvs(kvs.clone())
+            .await
+            .unwrap();
+        for writes in [1, 16] {
+            let store = Memory::test();
+            let mut cids = Vec::new();
+            let mut tree = CursorCreate::with_roller(&store, config)
+                .with_concurrency(writes)
+                .with_cids();
+            for (k, v) in kvs.iter() {
+                tree.push(*k, *v).await.unwrap();
+            }
+            assert_eq!(tree.finish_with_cids(&mut cids).await.unwrap(), root);
+            for cid in cids {
+                assert!(store.exists(&cid).await.unwrap(), "written node exists");
+            }
+            let streamed = CursorCreate::with_roller(&store, config)
+                .with_concurrency(writes)
+                .with_stream(stream::iter(kvs.clone().into_iter().map(Ok)))
+                .await
+                .unwrap();
+            assert_eq!(streamed, root);
+            let read = CursorRead::<_, u32, u32>::new(&store, streamed);
+            assert_eq!(read.range(..).await.unwrap(), kvs);
+        }
+        let store = Arc::new(ReadOnlyStore::default());
+        for writes in [1, 16] {
+            assert!(matches!(
+                CursorCreate::with_roller(&store, config)
+                    .with_concurrency(writes)
+                    .with_kvs(kvs.clone())
+                    .await,
+                Err(StoreError::ContentStore(_))
+            ));
+        }
+        let store = Memory::test();
+        let unsorted = stream::iter([(1u32, 0u32), (3, 0), (2, 0)].into_iter().map(Ok));
+        assert!(matches!(
+            CursorCreate::new(&store).with_stream(unsorted).await,
+            Err(StoreError::UnsortedKeys)
+        ));
+    }
+    /// Nodes of a single child collapsed out of the top of the tree are never written.
+    #[tokio::test]
+    async fn no_orphans() {
+        // A small pattern, such that levels often start with a node of a single child.
+        let config = RollerConfig::with_pattern((1 << 6) - 1);
+        for len in 0..300u32 {
+            let store = Memory::test();
+            let mut cids = Vec::new();
+            let mut tree = CursorCreate::with_roller(&store, config).with_cids();
+            for i in 0..len {
+                tree.push(i, i).await.unwrap();
+            }
+            let root = tree.finish_with_cids(&mut cids).await.unwrap();
+            verify::<_, u32, u32>(&store, root, config).await.unwrap();
+            assert_eq!(cids.len(), cids.iter().collect::<HashSet<_>>().len());
+            assert_eq!(
+                cids.into_iter().collect::<HashSet<_>>(),
+                nodes(&store, root).await
+            );
+        }
+    }
+}
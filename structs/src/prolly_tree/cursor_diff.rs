@@ -0,0 +1,319 @@
+use super::{cursor_read::height, Node};
+use fixity_store::{
+    content_store::ContentStore, contentid::Cid, deser::Deserialize, deser_ext::DeserExt,
+    store::StoreError,
+};
+use futures::stream::{self, Stream};
+use std::{cmp::Ordering, sync::Arc};
+
+/// A difference between two prolly trees, from an old tree to a new tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diff<K, V> {
+    /// A key value only in the new tree.
+    Added(K, V),
+    /// A key value only in the old tree.
+    Removed(K, V),
+    /// A key in both trees with different values, the old value followed by the new value.
+    Changed(K, V, V),
+}
+/// Walk two prolly trees together in key order, yielding the key values that differ.
+///
+/// Subtrees with the same Cid on both sides are skipped without being read, such that the cost
+/// of a diff depends on the size of the difference rather than the size of the trees.
+pub struct CursorDiff<'s, S, K, V> {
+    store: &'s Arc<S>,
+    /// The root nodes of the old and new trees, until the walk starts.
+    roots: Option<(Option<Cid>, Option<Cid>)>,
+    /// What remains of the old tree, in reverse order.
+    old: Vec<Item<K, V>>,
+    /// What remains of the new tree, in reverse order.
+    new: Vec<Item<K, V>>,
+}
+/// A part of a tree not yet compared.
+enum Item<K, V> {
+    /// A node not yet read.
+    Node {
+        /// The first key of the node, or `None` for the root.
+        key: Option<K>,
+        cid: Cid,
+        height: usize,
+    },
+    Entry(K, V),
+}
+impl<K, V> Item<K, V> {
+    fn key(&self) -> Option<&K> {
+        match self {
+            Self::Node { key, .. } => key.as_ref(),
+            Self::Entry(key, _) => Some(key),
+        }
+    }
+}
+impl<'s, S, K, V> CursorDiff<'s, S, K, V> {
+    /// Diff the tree with the `old` root node against the tree with the `new` root node.
+    pub fn new(store: &'s Arc<S>, old: Option<Cid>, new: Option<Cid>) -> Self {
+        Self {
+            store,
+            roots: Some((old, new)),
+            old: Vec::new(),
+            new: Vec::new(),
+        }
+    }
+}
+impl<'s, S, K, V> CursorDiff<'s, S, K, V>
+where
+    S: ContentStore,
+    K: Ord + Clone,
+    V: PartialEq,
+    Node<K, V>: Deserialize,
+{
+    /// The next difference in key order, or `None` once the trees are exhausted.
+    pub async fn next(&mut self) -> Result<Option<Diff<K, V>>, StoreError> {
+        if let Some((old, new)) = self.roots.take() {
+            if old != new {
+                self.old = self.root(old).await?;
+                self.new = self.root(new).await?;
+            }
+        }
+        loop {
+            let (old, new) = match (self.old.last(), self.new.last()) {
+                (None, None) => return Ok(None),
+                (Some(Item::Entry(..)), None) => {
+                    let (k, v) = pop_entry(&mut self.old);
+                    return Ok(Some(Diff::Removed(k, v)));
+                },
+                (None, Some(Item::Entry(..))) => {
+                    let (k, v) = pop_entry(&mut self.new);
+                    return Ok(Some(Diff::Added(k, v)));
+                },
+                (Some(Item::Node { .. }), None) => {
+                    expand(self.store, &mut self.old).await?;
+                    continue;
+                },
+                (None, Some(Item::Node { .. })) => {
+                    expand(self.store, &mut self.new).await?;
+                    continue;
+                },
+                (Some(old), Some(new)) => (old, new),
+            };
+            // A root node has no known first key, so it is compared as if aligned with the other
+            // side, leaving the heights to decide which side to read first.
+            let ordering = match (old.key(), new.key()) {
+                (Some(old), Some(new)) => old.cmp(new),
+                _ => Ordering::Equal,
+            };
+            match (old, new, ordering) {
+                (Item::Node { cid: old, .. }, Item::Node { cid: new, .. }, _) if old == new => {
+                    self.old.pop();
+                    self.new.pop();
+                },
+                (Item::Entry(..), _, Ordering::Less) => {
+                    let (k, v) = pop_entry(&mut self.old);
+                    return Ok(Some(Diff::Removed(k, v)));
+                },
+                (_, Item::Entry(..), Ordering::Greater) => {
+                    let (k, v) = pop_entry(&mut self.new);
+                    return Ok(Some(Diff::Added(k, v)));
+                },
+                (Item::Entry(..), Item::Entry(..), Ordering::Equal) => {
+                    let (k, old) = pop_entry(&mut self.old);
+                    let (_, new) = pop_entry(&mut self.new);
+                    if old != new {
+                        return Ok(Some(Diff::Changed(k, old, new)));
+                    }
+                },
+                (
+                    Item::Node {
+                        height: old_height, ..
+                    },
+                    Item::Node {
+                        height: new_height, ..
+                    },
+                    Ordering::Equal,
+                ) => {
+                    // Read the taller side first, as the shorter node may be shared with one of
+                    // its descendants.
+                    let (old_height, new_height) = (*old_height, *new_height);
+                    if old_height >= new_height {
+                        expand(self.store, &mut self.old).await?;
+                    }
+                    if new_height >= old_height {
+                        expand(self.store, &mut self.new).await?;
+                    }
+                },
+                (Item::Node { .. }, _, Ordering::Less | Ordering::Equal) => {
+                    expand(self.store, &mut self.old).await?;
+                },
+                (_, Item::Node { .. }, Ordering::Greater | Ordering::Equal) => {
+                    expand(self.store, &mut self.new).await?;
+                },
+            }
+        }
+    }
+    /// Stream every difference in key order.
+    pub fn into_stream(self) -> impl Stream<Item = Result<Diff<K, V>, StoreError>> + 's
+    where
+        K: 's,
+        V: 's,
+    {
+        stream::try_unfold(self, |mut cursor| async move {
+            Ok(cursor.next().await?.map(|diff| (diff, cursor)))
+        })
+    }
+    async fn root(&self, root: Option<Cid>) -> Result<Vec<Item<K, V>>, StoreError> {
+        let cid = match root {
+            Some(cid) => cid,
+            None => return Ok(Vec::new()),
+        };
+        Ok(vec![Item::Node {
+            key: None,
+            cid,
+            height: height::<S, Node<K, V>>(self.store, cid).await?,
+        }])
+    }
+}
+fn pop_entry<K, V>(items: &mut Vec<Item<K, V>>) -> (K, V) {
+    match items.pop() {
+        Some(Item::Entry(k, v)) => (k, v),
+        _ => unreachable!("entry checked by caller"),
+    }
+}
+/// Replace the node on top of the stack with its children.
+async fn expand<S, K, V>(store: &Arc<S>, items: &mut Vec<Item<K, V>>) -> Result<(), StoreError>
+where
+    S: ContentStore,
+    Node<K, V>: Deserialize,
+{
+    let (cid, height) = match items.pop() {
+        Some(Item::Node { cid, height, .. }) => (cid, height),
+        _ => unreachable!("node checked by caller"),
+    };
+    match store.get_owned_unchecked::<Node<K, V>>(&cid).await? {
+        Node::Branch(entries) => {
+            items.extend(entries.into_iter().rev().map(|(key, cid)| Item::Node {
+                key: Some(key),
+                cid,
+                height: height.saturating_sub(1),
+            }))
+        },
+        Node::Leaf(kvs) => items.extend(kvs.into_iter().rev().map(|(k, v)| Item::Entry(k, v))),
+    }
+    Ok(())
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::prolly_tree::{
+        cursor_create::CursorCreate, roller::Config as RollerConfig, test::TEST_PATTERN,
+    };
+    use fixity_store::{content_store::ContentStoreError, stores::memory::Memory};
+    use futures::TryStreamExt;
+    use proptest::collection::btree_map;
+    use std::{
+        collections::BTreeMap,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+    use test_strategy::proptest;
+
+    /// A store counting the blocks read from it.
+    #[derive(Debug, Default)]
+    pub(crate) struct CountingStore {
+        inner: Memory,
+        pub(crate) reads: AtomicUsize,
+    }
+    #[async_trait::async_trait]
+    impl ContentStore for CountingStore {
+        type Bytes = Arc<[u8]>;
+        async fn exists(&self, cid: &Cid) -> Result<bool, ContentStoreError> {
+            self.inner.exists(cid).await
+        }
+        async fn read_unchecked(&self, cid: &Cid) -> Result<Self::Bytes, ContentStoreError> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.inner.read_unchecked(cid).await
+        }
+        async fn write_unchecked<B>(&self, cid: &Cid, bytes: B) -> Result<(), ContentStoreError>
+        where
+            B: AsRef<[u8]> + Into<Arc<[u8]>> + Send,
+        {
+            self.inner.write_unchecked(cid, bytes).await
+        }
+    }
+    async fn create<S: ContentStore>(store: &Arc<S>, kvs: &BTreeMap<u32, u32>) -> Option<Cid> {
+        CursorCreate::with_roller(store, RollerConfig::with_pattern(TEST_PATTERN))
+            .with_kvs(kvs.clone().into_iter().collect())
+            .await
+            .unwrap()
+    }
+    /// The expected diff of two maps, in key order.
+    fn model_diff(old: &BTreeMap<u32, u32>, new: &BTreeMap<u32, u32>) -> Vec<Diff<u32, u32>> {
+        let mut keys = old.keys().chain(new.keys()).collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys.dedup();
+        keys.into_iter()
+            .filter_map(|k| match (old.get(k), new.get(k)) {
+                (Some(o), Some(n)) if o != n => Some(Diff::Changed(*k, *o, *n)),
+                (Some(o), None) => Some(Diff::Removed(*k, *o)),
+                (None, Some(n)) => Some(Diff::Added(*k, *n)),
+                _ => None,
+            })
+            .collect()
+    }
+    #[tokio::test]
+    async fn skips_shared_subtrees() {
+        let store = Arc::new(CountingStore::default());
+        let old = (0..10_000u32).map(|i| (i, i)).collect::<BTreeMap<_, _>>();
+        let mut new = old.clone();
+        new.insert(5_000, 0);
+        new.remove(&7_000);
+        new.insert(20_000, 1);
+        let (old_root, new_root) = (create(&store, &old).await, create(&store, &new).await);
+        store.reads.store(0, Ordering::Relaxed);
+        let diff = CursorDiff::new(&store, old_root, new_root)
+            .into_stream()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(diff, model_diff(&old, &new));
+        let reads = store.reads.load(Ordering::Relaxed);
+        assert!(reads < 100, "{} blocks read", reads);
+        store.reads.store(0, Ordering::Relaxed);
+        let mut same = CursorDiff::<_, u32, u32>::new(&store, new_root, new_root);
+        assert_eq!(same.next().await.unwrap(), None);
+        assert_eq!(store.reads.load(Ordering::Relaxed), 0);
+    }
+    #[proptest]
+    fn model(
+        #[strategy(btree_map(0..500u32, 0..3u32, 0..300))] old: BTreeMap<u32, u32>,
+        #[strategy(btree_map(0..500u32, 0..3u32, 0..300))] new: BTreeMap<u32, u32>,
+    ) {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async move {
+            let store = Memory::test();
+            // Share most of the entries, such that subtrees are skipped along the way.
+            let mut shared = (1_000..2_000u32)
+                .map(|i| (i, i))
+                .collect::<BTreeMap<_, _>>();
+            shared.extend(new);
+            let new = shared;
+            let mut shared = (1_000..2_000u32)
+                .map(|i| (i, i))
+                .collect::<BTreeMap<_, _>>();
+            shared.extend(old);
+            let old = shared;
+            let (old_root, new_root) = (create(&store, &old).await, create(&store, &new).await);
+            let diff = CursorDiff::new(&store, old_root, new_root)
+                .into_stream()
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(diff, model_diff(&old, &new));
+            let empty = CursorDiff::new(&store, None, new_root)
+                .into_stream()
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(empty, model_diff(&BTreeMap::new(), &new));
+        });
+    }
+}
@@ -0,0 +1,351 @@
+use super::{Node, TreeNode};
+use fixity_store::{
+    content_store::ContentStore, contentid::Cid, deser::Deserialize, deser_ext::DeserExt,
+    store::StoreError,
+};
+use futures::stream::{self, Stream, TryStreamExt};
+use std::{
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+    sync::Arc,
+};
+
+/// The default number of leaf nodes read ahead of a stream.
+pub const DEFAULT_PREFETCH: usize = 8;
+
+/// Read a prolly tree from the given root node.
+pub struct CursorRead<'s, S, K, V> {
+    store: &'s Arc<S>,
+    root: Option<Cid>,
+    prefetch: usize,
+    _kv: PhantomData<(K, V)>,
+}
+impl<'s, S, K, V> CursorRead<'s, S, K, V> {
+    pub fn new(store: &'s Arc<S>, root: Option<Cid>) -> Self {
+        Self {
+            store,
+            root,
+            prefetch: DEFAULT_PREFETCH,
+            _kv: PhantomData,
+        }
+    }
+    /// Read up to `prefetch` leaf nodes ahead of a stream concurrently, rather than one at a
+    /// time as the stream is consumed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefetch` is zero.
+    pub fn with_prefetch(mut self, prefetch: usize) -> Self {
+        assert!(prefetch > 0, "prefetch must read at least one node");
+        self.prefetch = prefetch;
+        self
+    }
+}
+impl<'s, S, K, V> CursorRead<'s, S, K, V>
+where
+    S: ContentStore,
+    K: Ord + Clone,
+    Node<K, V>: Deserialize,
+{
+    pub async fn get(&self, key: &K) -> Result<Option<V>, StoreError> {
+        let mut cid = match self.root {
+            Some(cid) => cid,
+            None => return Ok(None),
+        };
+        loop {
+            match self.store.get_owned_unchecked::<Node<K, V>>(&cid).await? {
+                Node::Branch(entries) => {
+                    // The child holding the key is the last child starting at or before it.
+                    let idx = entries.partition_point(|(k, _)| k <= key);
+                    match idx.checked_sub(1) {
+                        Some(idx) => cid = entries[idx].1,
+                        None => return Ok(None),
+                    }
+                },
+                Node::Leaf(mut kvs) => {
+                    return Ok(kvs
+                        .binary_search_by(|(k, _)| k.cmp(key))
+                        .ok()
+                        .map(|idx| kvs.swap_remove(idx).1));
+                },
+            }
+        }
+    }
+    /// The key values within the given range, in ascending order.
+    ///
+    /// Only the nodes overlapping the range are read.
+    pub async fn range<R>(&self, range: R) -> Result<Vec<(K, V)>, StoreError>
+    where
+        R: RangeBounds<K>,
+    {
+        let mut kvs = Vec::new();
+        // Nodes to read, in reverse order.
+        let mut stack = self.root.into_iter().collect::<Vec<_>>();
+        while let Some(cid) = stack.pop() {
+            match self.store.get_owned_unchecked::<Node<K, V>>(&cid).await? {
+                Node::Branch(entries) => {
+                    for (i, (start, cid)) in entries.iter().enumerate().rev() {
+                        let end = entries.get(i + 1).map(|(k, _)| k);
+                        if overlaps(&range, start, end) {
+                            stack.push(*cid);
+                        }
+                    }
+                },
+                Node::Leaf(leaf) => {
+                    kvs.extend(leaf.into_iter().filter(|(k, _)| range.contains(k)));
+                },
+            }
+        }
+        Ok(kvs)
+    }
+    /// Stream the key values within the given range, in ascending order.
+    ///
+    /// Like [`Self::range`], only the nodes overlapping the range are read. Leaf nodes are read
+    /// ahead of the stream, see [`Self::with_prefetch`].
+    pub fn stream<R>(&self, range: R) -> impl Stream<Item = Result<(K, V), StoreError>> + 's
+    where
+        R: RangeBounds<K> + Clone + Send + 's,
+        K: 's,
+        V: 's,
+    {
+        self.ordered_stream(range, false)
+    }
+    /// Like [`Self::stream`], but in descending order.
+    pub fn stream_rev<R>(&self, range: R) -> impl Stream<Item = Result<(K, V), StoreError>> + 's
+    where
+        R: RangeBounds<K> + Clone + Send + 's,
+        K: 's,
+        V: 's,
+    {
+        self.ordered_stream(range, true)
+    }
+    pub(crate) fn ordered_stream<R>(
+        &self,
+        range: R,
+        reverse: bool,
+    ) -> impl Stream<Item = Result<(K, V), StoreError>> + 's
+    where
+        R: RangeBounds<K> + Clone + Send + 's,
+        K: 's,
+        V: 's,
+    {
+        let store = self.store;
+        let leaves = Leaves {
+            store,
+            root: self.root,
+            stack: Vec::new(),
+            range: range.clone(),
+            reverse,
+            _kv: PhantomData,
+        };
+        stream::try_unfold(leaves, |mut leaves| async move {
+            Ok(leaves.next().await?.map(|cid| (cid, leaves)))
+        })
+        .map_ok(move |cid| async move { store.get_owned_unchecked::<Node<K, V>>(&cid).await })
+        .try_buffered(self.prefetch)
+        .map_ok(move |node| {
+            let kvs = match node {
+                Node::Leaf(kvs) => kvs,
+                Node::Branch(_) => unreachable!("nodes at the bottom of the tree are leaves"),
+            };
+            let mut kvs = kvs
+                .into_iter()
+                .filter(|(k, _)| range.contains(k))
+                .map(Ok)
+                .collect::<Vec<_>>();
+            if reverse {
+                kvs.reverse();
+            }
+            stream::iter(kvs)
+        })
+        .try_flatten()
+    }
+}
+/// The leaf nodes of a tree overlapping a range, in order.
+///
+/// Branch nodes are read one at a time as the leaves are walked, which is a small fraction of
+/// the nodes of a tree.
+struct Leaves<'s, S, K, V, R> {
+    store: &'s Arc<S>,
+    /// The root node, until the walk starts.
+    root: Option<Cid>,
+    /// The nodes to walk and their height, in reverse order.
+    stack: Vec<(Cid, usize)>,
+    range: R,
+    reverse: bool,
+    _kv: PhantomData<(K, V)>,
+}
+impl<'s, S, K, V, R> Leaves<'s, S, K, V, R>
+where
+    S: ContentStore,
+    K: Ord + Clone,
+    R: RangeBounds<K>,
+    Node<K, V>: Deserialize,
+{
+    async fn next(&mut self) -> Result<Option<Cid>, StoreError> {
+        if let Some(root) = self.root.take() {
+            let height = height::<S, Node<K, V>>(self.store, root).await?;
+            self.stack.push((root, height));
+        }
+        while let Some((cid, height)) = self.stack.pop() {
+            if height == 0 {
+                return Ok(Some(cid));
+            }
+            let entries = match self.store.get_owned_unchecked::<Node<K, V>>(&cid).await? {
+                Node::Branch(entries) => entries,
+                Node::Leaf(_) => unreachable!("nodes above the bottom of the tree are branches"),
+            };
+            let children = entries
+                .iter()
+                .enumerate()
+                .filter(|(i, (start, _))| {
+                    overlaps(&self.range, start, entries.get(i + 1).map(|(k, _)| k))
+                })
+                .map(|(_, (_, cid))| (*cid, height - 1))
+                .collect::<Vec<_>>();
+            if self.reverse {
+                self.stack.extend(children);
+            } else {
+                self.stack.extend(children.into_iter().rev());
+            }
+        }
+        Ok(None)
+    }
+}
+/// Whether the keys of a child node, from `start` up to but excluding `end`, may overlap the
+/// range.
+pub(crate) fn overlaps<K: Ord, R: RangeBounds<K>>(range: &R, start: &K, end: Option<&K>) -> bool {
+    let before_range_end = match range.end_bound() {
+        Bound::Included(k) => start <= k,
+        Bound::Excluded(k) => start < k,
+        Bound::Unbounded => true,
+    };
+    let after_range_start = match (range.start_bound(), end) {
+        (Bound::Included(k) | Bound::Excluded(k), Some(end)) => k < end,
+        (_, None) | (Bound::Unbounded, _) => true,
+    };
+    before_range_end && after_range_start
+}
+/// The number of branch levels above the leaves of the tree rooted at `cid`.
+pub(crate) async fn height<S, N>(store: &Arc<S>, cid: Cid) -> Result<usize, StoreError>
+where
+    S: ContentStore,
+    N: TreeNode + Deserialize,
+{
+    let mut cid = cid;
+    let mut height = 0;
+    while let Some(entries) = store.get_owned_unchecked::<N>(&cid).await?.entries() {
+        cid = entries.first().expect("branch is not empty").1;
+        height += 1;
+    }
+    Ok(height)
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::prolly_tree::{
+        cursor_create::CursorCreate, roller::Config as RollerConfig, test::TEST_PATTERN,
+    };
+    use fixity_store::{content_store::ContentStoreError, stores::memory::Memory};
+    use std::time::Duration;
+    #[tokio::test]
+    async fn read() {
+        let store = Memory::test();
+        let kvs = (0..1_000u32).map(|i| (i * 2, i)).collect::<Vec<_>>();
+        let root = CursorCreate::with_roller(&store, RollerConfig::with_pattern(TEST_PATTERN))
+            .with_kvs(kvs.clone())
+            .await
+            .unwrap();
+        let read = CursorRead::<_, u32, u32>::new(&store, root);
+        for (k, v) in kvs.iter() {
+            assert_eq!(read.get(k).await.unwrap(), Some(*v));
+            assert_eq!(read.get(&(k + 1)).await.unwrap(), None);
+        }
+        assert_eq!(read.range(..).await.unwrap(), kvs);
+        assert_eq!(
+            read.range(99..=104).await.unwrap(),
+            vec![(100, 50), (102, 51), (104, 52)]
+        );
+        assert_eq!(read.range(2_000..).await.unwrap(), vec![]);
+        let empty = CursorRead::<_, u32, u32>::new(&store, None);
+        assert_eq!(empty.get(&0).await.unwrap(), None);
+        assert_eq!(empty.range(..).await.unwrap(), vec![]);
+    }
+    #[tokio::test]
+    async fn stream() {
+        let store = Memory::test();
+        let kvs = (0..1_000u32).map(|i| (i * 2, i)).collect::<Vec<_>>();
+        let root = CursorCreate::with_roller(&store, RollerConfig::with_pattern(TEST_PATTERN))
+            .with_kvs(kvs.clone())
+            .await
+            .unwrap();
+        for prefetch in [1, 3, DEFAULT_PREFETCH] {
+            let read = CursorRead::<_, u32, u32>::new(&store, root).with_prefetch(prefetch);
+            let ranges = [
+                (Bound::Unbounded, Bound::Unbounded),
+                (Bound::Included(99), Bound::Included(104)),
+                (Bound::Excluded(100), Bound::Excluded(1_500)),
+                (Bound::Included(2_000), Bound::Unbounded),
+            ];
+            for range in ranges {
+                let expected = read.range(range).await.unwrap();
+                let streamed = read.stream(range).try_collect::<Vec<_>>().await.unwrap();
+                assert_eq!(streamed, expected);
+                let mut streamed = read
+                    .stream_rev(range)
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .unwrap();
+                streamed.reverse();
+                assert_eq!(streamed, expected);
+            }
+        }
+        let empty = CursorRead::<_, u32, u32>::new(&store, None);
+        assert_eq!(
+            empty.stream(..).try_collect::<Vec<_>>().await.unwrap(),
+            vec![]
+        );
+    }
+    /// A store taking a fixed time for every read.
+    #[derive(Debug, Default)]
+    struct SlowStore(Memory);
+    #[async_trait::async_trait]
+    impl ContentStore for SlowStore {
+        type Bytes = Arc<[u8]>;
+        async fn exists(&self, cid: &Cid) -> Result<bool, ContentStoreError> {
+            self.0.exists(cid).await
+        }
+        async fn read_unchecked(&self, cid: &Cid) -> Result<Self::Bytes, ContentStoreError> {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.0.read_unchecked(cid).await
+        }
+        async fn write_unchecked<B>(&self, cid: &Cid, bytes: B) -> Result<(), ContentStoreError>
+        where
+            B: AsRef<[u8]> + Into<Arc<[u8]>> + Send,
+        {
+            self.0.write_unchecked(cid, bytes).await
+        }
+    }
+    #[tokio::test(start_paused = true)]
+    async fn prefetch() {
+        let store = Arc::new(SlowStore::default());
+        let kvs = (0..5_000u32).map(|i| (i, i)).collect::<Vec<_>>();
+        let root = CursorCreate::with_roller(&store, RollerConfig::with_pattern(TEST_PATTERN))
+            .with_kvs(kvs.clone())
+            .await
+            .unwrap();
+        let mut elapsed = Vec::new();
+        for prefetch in [1, DEFAULT_PREFETCH] {
+            let read = CursorRead::<_, u32, u32>::new(&store, root).with_prefetch(prefetch);
+            let start = tokio::time::Instant::now();
+            assert_eq!(read.stream(..).try_collect::<Vec<_>>().await.unwrap(), kvs);
+            elapsed.push(start.elapsed());
+        }
+        assert!(
+            elapsed[1] * 3 < elapsed[0],
+            "prefetched scan took {:?}, against {:?} without",
+            elapsed[1],
+            elapsed[0]
+        );
+    }
+}
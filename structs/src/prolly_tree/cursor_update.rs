@@ -0,0 +1,226 @@
+use super::{cursor_create::Builder, cursor_read::height, roller::Config as RollerConfig, Node};
+use fixity_store::{
+    content_store::ContentStore,
+    contentid::Cid,
+    deser::{Deserialize, Serialize},
+    deser_ext::DeserExt,
+    store::StoreError,
+};
+use std::{collections::BTreeMap, mem, ops::Range, sync::Arc};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<V> {
+    Insert(V),
+    Remove,
+}
+/// Apply changes to an existing prolly tree, reusing every node the changes do not touch.
+pub struct CursorUpdate<'s, S, K: Clone, V> {
+    store: &'s Arc<S>,
+    root: Option<Cid>,
+    builder: Builder<'s, S, Node<K, V>>,
+}
+impl<'s, S, K: Clone, V> CursorUpdate<'s, S, K, V> {
+    pub fn new(store: &'s Arc<S>, root: Option<Cid>) -> Self {
+        Self::with_roller(store, root, RollerConfig::default())
+    }
+    pub fn with_roller(store: &'s Arc<S>, root: Option<Cid>, roller_config: RollerConfig) -> Self {
+        Self {
+            store,
+            root,
+            builder: Builder::new(store, roller_config),
+        }
+    }
+}
+/// A node of the existing tree waiting to be reused or rebuilt.
+struct Pending<K> {
+    /// The first key of the node, or `None` for the root.
+    key: Option<K>,
+    cid: Cid,
+    height: usize,
+    /// The changes falling within the keys of this node.
+    changes: Range<usize>,
+}
+impl<'s, S, K, V> CursorUpdate<'s, S, K, V>
+where
+    S: ContentStore,
+    K: Ord + Clone + Send + Sync,
+    V: Send + Sync,
+    Node<K, V>: Serialize + Deserialize,
+    (K, V): Serialize,
+{
+    /// Apply the changes, returning the new root node of the tree or `None` if the tree is empty.
+    pub async fn with_changes(
+        self,
+        changes: BTreeMap<K, Change<V>>,
+    ) -> Result<Option<Cid>, StoreError> {
+        self.update(changes, &mut Vec::new()).await
+    }
+    /// Like [`Self::with_changes`], but also reporting the Cid of every node written.
+    pub async fn apply(
+        mut self,
+        changes: BTreeMap<K, Change<V>>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<Option<Cid>, StoreError> {
+        self.builder.record_cids();
+        self.update(changes, cids_buf).await
+    }
+    async fn update(
+        mut self,
+        changes: BTreeMap<K, Change<V>>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<Option<Cid>, StoreError> {
+        let root = match self.root {
+            _ if changes.is_empty() => return Ok(self.root),
+            Some(root) => root,
+            None => {
+                for (k, change) in changes {
+                    if let Change::Insert(v) = change {
+                        self.builder.push((k, v)).await?;
+                    }
+                }
+                return self.builder.finish(cids_buf).await;
+            },
+        };
+        let mut changes = changes.into_iter().collect::<Vec<_>>();
+        let mut stack: Vec<Pending<K>> = vec![Pending {
+            key: None,
+            cid: root,
+            height: height::<S, Node<K, V>>(self.store, root).await?,
+            changes: 0..changes.len(),
+        }];
+        while let Some(pending) = stack.pop() {
+            if let Some(key) = pending.key.as_ref() {
+                if pending.changes.is_empty() && self.builder.is_aligned(pending.height) {
+                    self.builder
+                        .push_node(pending.height, key.clone(), pending.cid)
+                        .await?;
+                    continue;
+                }
+            }
+            match self
+                .store
+                .get_owned_unchecked::<Node<K, V>>(&pending.cid)
+                .await?
+            {
+                Node::Branch(entries) => {
+                    // Each child gets the changes up to the key of the next child, such that
+                    // keys before the first child fall in the first child.
+                    let Range { mut start, end } = pending.changes;
+                    let mut children = Vec::with_capacity(entries.len());
+                    let mut entries = entries.into_iter().peekable();
+                    while let Some((key, cid)) = entries.next() {
+                        let child_end = match entries.peek() {
+                            Some((next_key, _)) => {
+                                start + changes[start..end].partition_point(|(k, _)| k < next_key)
+                            },
+                            None => end,
+                        };
+                        children.push(Pending {
+                            key: Some(key),
+                            cid,
+                            height: pending.height - 1,
+                            changes: start..child_end,
+                        });
+                        start = child_end;
+                    }
+                    stack.extend(children.into_iter().rev());
+                },
+                Node::Leaf(kvs) => {
+                    let mut changes = changes[pending.changes].iter_mut().peekable();
+                    let mut kvs = kvs.into_iter().peekable();
+                    loop {
+                        let next_is_change = match (kvs.peek(), changes.peek()) {
+                            (_, None) => false,
+                            (None, Some(_)) => true,
+                            (Some((k, _)), Some((change_k, _))) => change_k <= k,
+                        };
+                        if next_is_change {
+                            let (k, change) = changes.next().expect("peeked change");
+                            let (k, change) = (k.clone(), mem::replace(change, Change::Remove));
+                            // The change replaces or removes the existing value of the key.
+                            if matches!(kvs.peek(), Some((existing_k, _)) if *existing_k == k) {
+                                kvs.next();
+                            }
+                            if let Change::Insert(v) = change {
+                                self.builder.push((k, v)).await?;
+                            }
+                        } else {
+                            match kvs.next() {
+                                Some(kv) => self.builder.push(kv).await?,
+                                None => break,
+                            }
+                        }
+                    }
+                },
+            }
+        }
+        self.builder.finish(cids_buf).await
+    }
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::prolly_tree::{cursor_create::CursorCreate, test::TEST_PATTERN};
+    use fixity_store::stores::memory::Memory;
+    #[tokio::test]
+    async fn update_matches_create() {
+        let store = Memory::test();
+        let config = RollerConfig::with_pattern(TEST_PATTERN);
+        let kvs = (0..2_000u32).map(|i| (i, i)).collect::<Vec<_>>();
+        let root = CursorCreate::with_roller(&store, config)
+            .with_kvs(kvs.clone())
+            .await
+            .unwrap();
+        let changes = [
+            (0, Change::Remove),
+            (500, Change::Insert(0)),
+            (1_000, Change::Remove),
+            (1_001, Change::Remove),
+            (3_000, Change::Insert(3_000)),
+        ];
+        let mut expected = kvs.into_iter().collect::<BTreeMap<_, _>>();
+        for (k, change) in changes.iter().cloned() {
+            match change {
+                Change::Insert(v) => expected.insert(k, v),
+                Change::Remove => expected.remove(&k),
+            };
+        }
+        let updated = CursorUpdate::with_roller(&store, root, config)
+            .with_changes(changes.into_iter().collect())
+            .await
+            .unwrap();
+        let created = CursorCreate::with_roller(&store, config)
+            .with_kvs(expected.into_iter().collect())
+            .await
+            .unwrap();
+        assert_eq!(updated, created);
+        let removed = CursorUpdate::<_, u32, u32>::with_roller(&store, created, config)
+            .with_changes((0..3_001).map(|k| (k, Change::Remove)).collect())
+            .await
+            .unwrap();
+        assert_eq!(removed, None);
+    }
+    #[tokio::test]
+    async fn truncate_matches_create() {
+        let store = Memory::test();
+        let config = RollerConfig::with_pattern(TEST_PATTERN);
+        let kvs = (0..2_000u32).map(|i| (i, i)).collect::<Vec<_>>();
+        let root = CursorCreate::with_roller(&store, config)
+            .with_kvs(kvs.clone())
+            .await
+            .unwrap();
+        // Removing every key past a reused node leaves it as the root, which must collapse like a
+        // created tree would.
+        for len in 1..2_000 {
+            let updated = CursorUpdate::<_, u32, u32>::with_roller(&store, root, config)
+                .with_changes((len..2_000).map(|k| (k, Change::Remove)).collect())
+                .await
+                .unwrap();
+            let created = CursorCreate::with_roller(&store, config)
+                .with_kvs(kvs[..len as usize].to_vec())
+                .await
+                .unwrap();
+            assert_eq!(updated, created, "len {len}");
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use fixity_store::store::StoreError;
+use std::fmt;
+
+/// Resolve a key changed differently on both sides of a three-way merge.
+pub trait ConflictResolver<K, V>: Send + Sync {
+    /// Resolve the value of `key`, given the value of the common ancestor and of both sides,
+    /// returning the merged value or `None` to remove the key.
+    ///
+    /// A `None` value is a key absent from that version of the tree.
+    fn resolve(
+        &self,
+        key: &K,
+        ancestor: Option<&V>,
+        ours: Option<&V>,
+        theirs: Option<&V>,
+    ) -> Result<Option<V>, StoreError>;
+}
+impl<K, V, F> ConflictResolver<K, V> for F
+where
+    F: Fn(&K, Option<&V>, Option<&V>, Option<&V>) -> Result<Option<V>, StoreError> + Send + Sync,
+{
+    fn resolve(
+        &self,
+        key: &K,
+        ancestor: Option<&V>,
+        ours: Option<&V>,
+        theirs: Option<&V>,
+    ) -> Result<Option<V>, StoreError> {
+        self(key, ancestor, ours, theirs)
+    }
+}
+impl<K, V> fmt::Debug for dyn ConflictResolver<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ConflictResolver")
+    }
+}
+/// The default [`ConflictResolver`], failing the merge on any conflict.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RejectConflicts;
+impl<K, V> ConflictResolver<K, V> for RejectConflicts {
+    fn resolve(
+        &self,
+        _: &K,
+        _: Option<&V>,
+        _: Option<&V>,
+        _: Option<&V>,
+    ) -> Result<Option<V>, StoreError> {
+        Err(StoreError::UnmergableType)
+    }
+}
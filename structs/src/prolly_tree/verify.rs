@@ -0,0 +1,246 @@
+use super::{
+    roller::{Config as RollerConfig, Roller},
+    Node,
+};
+use fixity_store::{
+    content_store::ContentStore,
+    contentid::Cid,
+    deser::{Deserialize, Serialize},
+    deser_ext::DeserExt,
+    store::StoreError,
+};
+use std::sync::Arc;
+use thiserror::Error;
+
+/// A way in which a prolly tree differs from the tree its entries would produce.
+#[derive(Error, Debug)]
+pub enum VerifyError {
+    #[error("store: {0}")]
+    Store(#[from] StoreError),
+    #[error("node {cid} is empty")]
+    EmptyNode { cid: Cid },
+    #[error("root {cid} is a branch with a single child")]
+    SingleChildRoot { cid: Cid },
+    #[error("keys of node {cid} are not in ascending order")]
+    UnsortedKeys { cid: Cid },
+    #[error("first key of node {cid} does not match the key of its branch entry")]
+    BranchKey { cid: Cid },
+    #[error("leaf {cid} is at a different depth than the leaves before it")]
+    UnevenDepth { cid: Cid },
+    #[error("node {cid} has a boundary before its last item")]
+    EarlyBoundary { cid: Cid },
+    #[error("node {cid} ends without a boundary, but is not the last node of its level")]
+    MissingBoundary { cid: Cid },
+}
+/// A node waiting to be verified.
+struct Pending<K> {
+    cid: Cid,
+    depth: usize,
+    /// The key of the branch entry pointing to the node, or `None` for the root.
+    key: Option<K>,
+    /// Whether the node is the last node of its level.
+    last: bool,
+}
+/// Verify the tree with the given root node is the tree its entries produce with the given roller,
+/// reading every node of the tree.
+///
+/// Along with the invariants of the tree itself, such as key order and balance, every node must
+/// end exactly at the first boundary found by the roller, making the tree independent of the
+/// history of changes that produced it.
+pub async fn verify<S, K, V>(
+    store: &Arc<S>,
+    root: Option<Cid>,
+    roller_config: RollerConfig,
+) -> Result<(), VerifyError>
+where
+    S: ContentStore,
+    K: Ord + Clone,
+    Node<K, V>: Deserialize,
+    (K, V): Serialize,
+{
+    let mut roller = Roller::with_config(roller_config);
+    let mut stack = root
+        .into_iter()
+        .map(|cid| Pending {
+            cid,
+            depth: 0,
+            key: None,
+            last: true,
+        })
+        .collect::<Vec<_>>();
+    let mut leaf_depth = None;
+    // The last key of the leaves verified so far, as leaves are verified in key order.
+    let mut last_key = None::<K>;
+    while let Some(Pending {
+        cid,
+        depth,
+        key,
+        last,
+    }) = stack.pop()
+    {
+        let node = store.get_owned_unchecked::<Node<K, V>>(&cid).await?;
+        let (first_key, boundaries) = match &node {
+            Node::Branch(entries) if entries.len() == 1 && depth == 0 => {
+                return Err(VerifyError::SingleChildRoot { cid });
+            },
+            Node::Branch(entries) => {
+                if !entries.windows(2).all(|w| w[0].0 < w[1].0) {
+                    return Err(VerifyError::UnsortedKeys { cid });
+                }
+                for (i, (key, child)) in entries.iter().enumerate().rev() {
+                    stack.push(Pending {
+                        cid: *child,
+                        depth: depth + 1,
+                        key: Some(key.clone()),
+                        last: last && i + 1 == entries.len(),
+                    });
+                }
+                let boundaries = entries
+                    .iter()
+                    .map(|(_, child)| roller.roll_item(child.as_ref()))
+                    .collect::<Vec<_>>();
+                (entries.first().map(|(k, _)| k), boundaries)
+            },
+            Node::Leaf(kvs) => {
+                if *leaf_depth.get_or_insert(depth) != depth {
+                    return Err(VerifyError::UnevenDepth { cid });
+                }
+                for (k, _) in kvs.iter() {
+                    if matches!(&last_key, Some(last_key) if last_key >= k) {
+                        return Err(VerifyError::UnsortedKeys { cid });
+                    }
+                    last_key = Some(k.clone());
+                }
+                let boundaries = kvs
+                    .iter()
+                    .map(|kv| Ok(roller.roll_item(kv.serialize()?.as_ref())))
+                    .collect::<Result<Vec<_>, StoreError>>()?;
+                (kvs.first().map(|(k, _)| k), boundaries)
+            },
+        };
+        let (end, items) = match boundaries.split_last() {
+            Some(split) => split,
+            None => return Err(VerifyError::EmptyNode { cid }),
+        };
+        if key.is_some() && key.as_ref() != first_key {
+            return Err(VerifyError::BranchKey { cid });
+        }
+        if items.iter().any(|boundary| *boundary) {
+            return Err(VerifyError::EarlyBoundary { cid });
+        }
+        // The last node of a level ends with the entries rather than a boundary.
+        if !end && !last {
+            return Err(VerifyError::MissingBoundary { cid });
+        }
+    }
+    Ok(())
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::prolly_tree::{
+        cursor_create::CursorCreate,
+        cursor_update::{Change, CursorUpdate},
+        test::TEST_PATTERN,
+        ProllyTree,
+    };
+    use fixity_store::{container::PersistContainer, stores::memory::Memory};
+    use futures::stream;
+    use proptest::{collection::vec, sample::Index};
+    use std::collections::BTreeMap;
+    use test_strategy::proptest;
+
+    fn config() -> RollerConfig {
+        RollerConfig::with_pattern(TEST_PATTERN)
+    }
+    async fn create(store: &Arc<Memory>, kvs: &BTreeMap<u16, u16>) -> Option<Cid> {
+        CursorCreate::with_roller(store, config())
+            .with_kvs(kvs.clone().into_iter().collect())
+            .await
+            .unwrap()
+    }
+    #[tokio::test]
+    async fn detects_other_roller() {
+        let store = Memory::test();
+        let kvs = (0..2_000u16).map(|i| (i, i)).collect::<BTreeMap<_, _>>();
+        let root = create(&store, &kvs).await;
+        verify::<_, u16, u16>(&store, root, config()).await.unwrap();
+        let other = RollerConfig::with_pattern((1 << 6) - 1);
+        assert!(matches!(
+            verify::<_, u16, u16>(&store, root, other).await,
+            Err(VerifyError::EarlyBoundary { .. } | VerifyError::MissingBoundary { .. })
+        ));
+        verify::<_, u16, u16>(&store, None, config()).await.unwrap();
+    }
+    /// Every way of building the same entries produces the same, well formed, tree.
+    #[proptest]
+    fn history_independent(
+        #[strategy(vec(0..2_000u16, 0..400))] keys: Vec<u16>,
+        #[strategy(vec(0..2_000u16, 0..100))] removed: Vec<u16>,
+        #[strategy(1..100usize)] batch_size: usize,
+        shuffle: Vec<Index>,
+    ) {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async move {
+            let store = Memory::test();
+            let kvs = keys
+                .iter()
+                .filter(|k| !removed.contains(k))
+                .map(|k| (*k, k.wrapping_mul(7)))
+                .collect::<BTreeMap<_, _>>();
+            let created = create(&store, &kvs).await;
+            verify::<_, u16, u16>(&store, created, config())
+                .await
+                .unwrap();
+            let streamed = CursorCreate::with_roller(&store, config())
+                .with_stream(stream::iter(kvs.clone().into_iter().map(Ok)))
+                .await
+                .unwrap();
+            assert_eq!(streamed, created);
+            // Insert every key in batches of a shuffled order, then remove the removed keys in
+            // batches, verifying every intermediate tree.
+            let mut order = keys.clone();
+            for (i, index) in shuffle.iter().enumerate().take(order.len()) {
+                let j = index.index(order.len());
+                order.swap(i, j);
+            }
+            let mut root = None;
+            let inserts = order
+                .iter()
+                .map(|k| (*k, Change::Insert(k.wrapping_mul(7))));
+            let removes = removed.iter().map(|k| (*k, Change::Remove));
+            let changes = inserts.chain(removes).collect::<Vec<_>>();
+            for batch in changes.chunks(batch_size) {
+                root = CursorUpdate::with_roller(&store, root, config())
+                    .with_changes(batch.iter().cloned().collect())
+                    .await
+                    .unwrap();
+                verify::<_, u16, u16>(&store, root, config()).await.unwrap();
+            }
+            assert_eq!(root, created);
+            // The same through a tree, saving between batches.
+            let mut tree = ProllyTree::with_roller(config());
+            for batch in changes.chunks(batch_size) {
+                for (k, change) in batch.iter().cloned() {
+                    match change {
+                        Change::Insert(v) => tree.insert(k, v),
+                        Change::Remove => tree.remove(k),
+                    }
+                }
+                tree.save(&store).await.unwrap();
+            }
+            assert_eq!(tree.node, created);
+            // As is the root itself.
+            let mut fresh = ProllyTree::with_roller(config());
+            for (k, v) in kvs {
+                fresh.insert(k, v);
+            }
+            assert_eq!(
+                tree.save(&store).await.unwrap(),
+                fresh.save(&store).await.unwrap()
+            );
+        });
+    }
+}
@@ -1,49 +1,245 @@
+use fixity_store::{
+    container::PersistContainer, content_store::ContentStore, contentid::Cid, store::StoreError,
+};
 use std::{
     collections::HashMap,
+    mem,
     ops::{Deref, DerefMut},
-    sync::Arc,
+    sync::{Arc, Weak},
 };
 
-// NIT: Is there something cheaper than Arc? Since
-// i don't care about using the Rc portion of Arc.
-pub struct Ptr<Cid, T>(Arc<PtrInner<Cid, T>>);
-enum PtrInner<Cid, T> {
-    Ptr { cid: Cid },
-    Ref { cid: Cid, value: T },
+/// A pointer to a value stored as its own block, loaded from the store on first access through a
+/// [`Resolver`] and only saved again once changed.
+#[derive(Debug, Clone)]
+pub struct Ptr<C, T>(PtrInner<C, T>);
+#[derive(Debug, Clone)]
+enum PtrInner<C, T> {
+    /// A value not yet loaded from the store.
+    Ptr { cid: C },
+    /// A loaded value, unchanged since it was stored as `cid`.
+    ///
+    /// The value is shared with every other pointer to `cid` resolved by the same [`Resolver`].
+    //
+    // NIT: Is there something cheaper than Arc? Since
+    // i don't care about using the Rc portion of Arc.
+    Ref { cid: C, value: Arc<T> },
+    /// A value changed since it was loaded, or never stored at all.
     Mut { value: T },
 }
-pub struct Registry<Cid, Owner, T>((Owner, Resolver<Cid, T>));
-impl<Cid, Owner, T> Registry<Cid, Owner, T> {
+impl<C, T> Ptr<C, T> {
+    /// A pointer to a new value, which will be written on the next save.
+    pub fn new(value: T) -> Self {
+        Self(PtrInner::Mut { value })
+    }
+    /// A pointer to a stored value, which will be loaded on first access.
+    pub fn from_cid(cid: C) -> Self {
+        Self(PtrInner::Ptr { cid })
+    }
+    /// The Cid the value is stored as, or `None` if the value has changed since.
+    pub fn cid(&self) -> Option<&C> {
+        match &self.0 {
+            PtrInner::Ptr { cid } | PtrInner::Ref { cid, .. } => Some(cid),
+            PtrInner::Mut { .. } => None,
+        }
+    }
+    /// Whether the value is known to be stored as `cid`.
+    pub fn is_stored_as(&self, cid: &C) -> bool
+    where
+        C: PartialEq,
+    {
+        self.cid() == Some(cid)
+    }
+    /// The value, if already loaded.
+    pub fn get(&self) -> Option<&T> {
+        match &self.0 {
+            PtrInner::Ptr { .. } => None,
+            PtrInner::Ref { value, .. } => Some(value),
+            PtrInner::Mut { value } => Some(value),
+        }
+    }
+}
+impl<T> Ptr<Cid, T> {
+    /// The Cid of the value, saving it first if it has changed.
+    pub async fn save_with_cids<S>(
+        &mut self,
+        store: &Arc<S>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<Cid, StoreError>
+    where
+        S: ContentStore,
+        T: PersistContainer<S>,
+    {
+        let value = match &mut self.0 {
+            PtrInner::Ptr { cid } | PtrInner::Ref { cid, .. } => return Ok(*cid),
+            PtrInner::Mut { value } => value,
+        };
+        value.save_with_cids(store, cids_buf).await?;
+        let cid = *cids_buf.last().expect("value cid written");
+        self.0 = match mem::replace(&mut self.0, PtrInner::Ptr { cid }) {
+            PtrInner::Mut { value } => PtrInner::Ref {
+                cid,
+                value: Arc::new(value),
+            },
+            _ => unreachable!("value saved above"),
+        };
+        Ok(cid)
+    }
+}
+/// A value paired with the [`Resolver`] of the pointers it holds.
+pub struct Registry<C, Owner, T>((Owner, Resolver<C, T>));
+impl<C, Owner, T> Registry<C, Owner, T> {
     pub fn new(owner: Owner) -> Self {
         Self((owner, Default::default()))
     }
 }
-impl<Cid, Owner, T> Deref for Registry<Cid, Owner, T> {
-    type Target = (Owner, Resolver<Cid, T>);
+impl<C, Owner, T> Deref for Registry<C, Owner, T> {
+    type Target = (Owner, Resolver<C, T>);
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
-impl<Cid, Owner, T> DerefMut for Registry<Cid, Owner, T> {
+impl<C, Owner, T> DerefMut for Registry<C, Owner, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.0
     }
 }
-pub struct Resolver<Cid, T> {
-    weak_ptrs: HashMap<Cid, Ptr<Cid, T>>,
+/// Loads the values of [`Ptr`]s, sharing a single loaded value between every pointer to the same
+/// Cid for as long as any of them hold it.
+#[derive(Debug, Clone)]
+pub struct Resolver<C, T> {
+    weak_ptrs: HashMap<C, Weak<T>>,
 }
-impl<Cid, T> Resolver<Cid, T> {
-    // pub async fn resolve<S: Store>(&mut self, store: &S, ptr: &Ptr<Cid, T>) -> &T {
-    //     todo!()
-    // }
-    // pub async fn resolve_mut<S: Store>(&mut self, store: &S, ptr: &mut Ptr<Cid, T>) -> &mut T {
-    //     todo!()
-    // }
+impl<T> Resolver<Cid, T> {
+    /// Load the value of the pointer if needed.
+    pub async fn resolve<'p, S>(
+        &mut self,
+        store: &Arc<S>,
+        ptr: &'p mut Ptr<Cid, T>,
+    ) -> Result<&'p T, StoreError>
+    where
+        S: ContentStore,
+        T: PersistContainer<S>,
+    {
+        if let PtrInner::Ptr { cid } = ptr.0 {
+            let value = match self.weak_ptrs.get(&cid).and_then(Weak::upgrade) {
+                Some(value) => value,
+                None => {
+                    let value = Arc::new(T::open(store, &cid).await?);
+                    // Values no longer held by any pointer are dropped before the map would grow,
+                    // bounding it by the values still held.
+                    if self.weak_ptrs.len() == self.weak_ptrs.capacity() {
+                        self.weak_ptrs.retain(|_, value| value.strong_count() > 0);
+                    }
+                    self.weak_ptrs.insert(cid, Arc::downgrade(&value));
+                    value
+                },
+            };
+            ptr.0 = PtrInner::Ref { cid, value };
+        }
+        Ok(ptr.get().expect("value loaded above"))
+    }
+    /// Like [`Self::resolve`], but marks the value as changed such that it is written on the next
+    /// save.
+    pub async fn resolve_mut<'p, S>(
+        &mut self,
+        store: &Arc<S>,
+        ptr: &'p mut Ptr<Cid, T>,
+    ) -> Result<&'p mut T, StoreError>
+    where
+        S: ContentStore,
+        T: PersistContainer<S> + Clone,
+    {
+        if let PtrInner::Ptr { cid } | PtrInner::Ref { cid, .. } = ptr.0 {
+            let value = match mem::replace(&mut ptr.0, PtrInner::Ptr { cid }) {
+                // A shared value is left to the other pointers, changing a copy of it instead.
+                PtrInner::Ref { value, .. } => {
+                    Arc::try_unwrap(value).unwrap_or_else(|value| T::clone(&value))
+                },
+                _ => T::open(store, &cid).await?,
+            };
+            ptr.0 = PtrInner::Mut { value };
+        }
+        match &mut ptr.0 {
+            PtrInner::Mut { value } => Ok(value),
+            _ => unreachable!("value set above"),
+        }
+    }
 }
-impl<Cid, T> Default for Resolver<Cid, T> {
+impl<C, T> Default for Resolver<C, T> {
     fn default() -> Self {
         Self {
             weak_ptrs: Default::default(),
         }
     }
 }
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::{gcounter::GCounter, prolly_tree::cursor_diff::test::CountingStore};
+    use fixity_store::stores::memory::Memory;
+    use std::sync::atomic::Ordering;
+    #[tokio::test]
+    async fn resolve() {
+        let store = Arc::new(CountingStore::default());
+        let mut counter = GCounter::new();
+        counter.inc(1.into());
+        let mut ptr = Ptr::<Cid, _>::new(counter);
+        let mut cids = Vec::new();
+        let cid = ptr.save_with_cids(&store, &mut cids).await.unwrap();
+        assert_eq!(cids, vec![cid]);
+        assert!(ptr.is_stored_as(&cid));
+        let mut resolver = Resolver::<Cid, GCounter>::default();
+        let (mut a, mut b) = (Ptr::from_cid(cid), Ptr::from_cid(cid));
+        assert!(a.get().is_none());
+        assert_eq!(resolver.resolve(&store, &mut a).await.unwrap().value(), 1);
+        resolver.resolve(&store, &mut b).await.unwrap();
+        assert!(
+            std::ptr::eq(a.get().unwrap(), b.get().unwrap()),
+            "pointers to the same cid share the value"
+        );
+        // Reading a value does not change it.
+        assert_eq!(
+            a.save_with_cids(&store, &mut Vec::new()).await.unwrap(),
+            cid
+        );
+        store.reads.store(0, Ordering::Relaxed);
+        resolver
+            .resolve_mut(&store, &mut a)
+            .await
+            .unwrap()
+            .inc(2.into());
+        assert_eq!(a.cid(), None);
+        assert_eq!(
+            store.reads.load(Ordering::Relaxed),
+            0,
+            "a shared value is copied rather than loaded again"
+        );
+        assert_eq!(b.get().unwrap().value(), 1, "other pointers are unchanged");
+        let mut cids = Vec::new();
+        let changed = a.save_with_cids(&store, &mut cids).await.unwrap();
+        assert_eq!(cids, vec![changed]);
+        assert_ne!(changed, cid);
+    }
+    #[tokio::test]
+    async fn drops_released_values() {
+        let store = Memory::test();
+        let mut resolver = Resolver::<Cid, GCounter>::default();
+        for i in 1..100 {
+            let mut counter = GCounter::new();
+            counter.inc(i.into());
+            let cid = Ptr::<Cid, _>::new(counter)
+                .save_with_cids(&store, &mut Vec::new())
+                .await
+                .unwrap();
+            resolver
+                .resolve(&store, &mut Ptr::from_cid(cid))
+                .await
+                .unwrap();
+        }
+        assert!(
+            resolver.weak_ptrs.len() < 10,
+            "{} values tracked",
+            resolver.weak_ptrs.len()
+        );
+    }
+}
@@ -5,13 +5,14 @@ use fixity_store::{
         ReconcileContainer,
     },
     content_store::ContentStore,
-    contentid::Cid,
+    contentid::{Cid, ContentId},
     deser_ext::DeserExt,
     replicaid::Rid,
+    replicakey::{ReplicaKey, ReplicaSig},
     store::StoreError,
 };
 use std::{
-    collections::{btree_map, BTreeMap, BTreeSet},
+    collections::{btree_map, BTreeMap},
     sync::Arc,
 };
 
@@ -36,12 +37,63 @@ pub struct ReplicaLog<S> {
     // needing to track one thing, this value.
     tip_cid: Option<Cid>,
     tip: LogEntry,
+    /// The replica this log belongs to, known once the log is opened or given a key.
+    replica: Option<Rid>,
+    /// The key used to sign entries on save. Opened logs are read only until a key is set.
+    key: Option<ReplicaKey>,
     _store: Arc<S>,
 }
+impl<S> ReplicaLog<S> {
+    /// Construct a new, empty log for the replica of the given key.
+    pub fn with_key(store: &Arc<S>, key: ReplicaKey) -> Self {
+        ReplicaLog {
+            clean: true,
+            tip_cid: None,
+            tip: Default::default(),
+            replica: Some(key.rid()),
+            key: Some(key),
+            _store: Arc::clone(store),
+        }
+    }
+    /// Set the key used to sign entries on save.
+    ///
+    /// If this log already belongs to a replica, the key must be of that same replica.
+    pub fn set_key(&mut self, key: ReplicaKey) -> Result<(), StoreError> {
+        let rid = key.rid();
+        match self.replica {
+            Some(expected) if expected != rid => {
+                return Err(StoreError::UnexpectedReplica { expected, got: rid })
+            },
+            _ => {},
+        }
+        self.replica = Some(rid);
+        self.key = Some(key);
+        Ok(())
+    }
+    /// The replica that this log belongs to, if known.
+    pub fn replica(&self) -> Option<Rid> {
+        self.replica
+    }
+}
 impl<S> ReplicaLog<S>
 where
     S: ContentStore,
 {
+    /// Open the log at the given cid, verifying that it was signed by the expected replica.
+    pub async fn open_replica(
+        store: &Arc<S>,
+        cid: &Cid,
+        replica: Rid,
+    ) -> Result<ReplicaLog<S>, StoreError> {
+        let log = Self::open(store, cid).await?;
+        match log.replica {
+            Some(got) if got != replica => Err(StoreError::UnexpectedReplica {
+                expected: replica,
+                got,
+            }),
+            _ => Ok(log),
+        }
+    }
     pub fn repo_tip(&self, repo_name: &str) -> Option<Cid> {
         self.tip
             .repos
@@ -49,6 +101,34 @@ where
             .get(repo_name)
             .map(|repo| repo.branch_tip)
     }
+    /// Load the [`Identity`] of this replica, or an empty `Identity` if none has been claimed.
+    pub async fn identity(&self, store: &Arc<S>) -> Result<Identity, StoreError> {
+        match self.tip.identity.as_ref() {
+            Some(cid) => store.get_owned_unchecked::<Identity>(cid).await,
+            None => Ok(Identity::default()),
+        }
+    }
+    /// Claim the given replica as part of the identity of this replica.
+    ///
+    /// The `ack` is the signature of the claimed replica acknowledging the claim, as produced by
+    /// [`ack_claim`]. Paired with the signature of this log, both replicas have signed the claim.
+    pub async fn claim_replica(
+        &mut self,
+        store: &Arc<S>,
+        rid: Rid,
+        ack: ReplicaSig,
+    ) -> Result<(), StoreError> {
+        let claimer = self.replica.ok_or(StoreError::MissingReplicaKey)?;
+        rid.verify(&claim_cid(&claimer, &rid), &ack)?;
+        let mut identity = self.identity(store).await?;
+        if identity.claimed_replicas.get(&rid) == Some(&ack) {
+            return Ok(());
+        }
+        identity.claimed_replicas.insert(rid, ack);
+        self.tip.identity = Some(store.put(&identity).await?);
+        self.clean = false;
+        Ok(())
+    }
     pub fn set_repo_tip(&mut self, repo: &str, cid: Cid) {
         let modified = match self.tip.repos.repos.entry(repo.to_string()) {
             btree_map::Entry::Vacant(entry) => {
@@ -76,17 +156,25 @@ impl<S> DescribeContainer for ReplicaLog<S> {
         }
     }
 }
-// // TODO: Placeholder for signature chain. Need to mock up
-// // replica sig and identity sig.
-// #[cfg_attr(
-//     feature = "rkyv",
-//     derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)
-// )]
-// #[derive(Debug)]
-// pub struct SignedLogEntry<Rid, Cid> {
-//     pub entry: LogEntry<Rid,Cid>,
-//     pub replica_sig: (),
-// }
+/// A [`LogEntry`] signed by the replica that wrote it. The tip of a [`ReplicaLog`] is always a
+/// `SignedLogEntry`.
+///
+/// Since each `LogEntry` includes the Cid of the previous `SignedLogEntry`, the signature of the
+/// tip covers the entire history of the log.
+//
+// TODO: Add an identity sig, once identities are signed.
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)
+)]
+#[derive(Debug)]
+pub struct SignedLogEntry {
+    /// The [`LogEntry`] pointer being signed.
+    pub entry: Cid,
+    /// The replica that signed the entry, aka the public key of the signature.
+    pub replica: Rid,
+    pub replica_sig: ReplicaSig,
+}
 // TODO: Make this into an enum. A bit annoying perhaps, but correct, and that's nice.
 #[cfg_attr(
     feature = "rkyv",
@@ -94,15 +182,14 @@ impl<S> DescribeContainer for ReplicaLog<S> {
 )]
 #[derive(Debug, Default)]
 pub struct LogEntry {
+    /// The previous [`SignedLogEntry`] pointer.
     pub previous: Option<Cid>,
     /// [`Defaults`] pointer.
     pub defaults: Option<Cid>,
     /// Embedded [`Repos`] where each [`Repo`] tracks the tip of the active branch.
     pub repos: Repos,
-    // /// An [`Identity`] pointer for this Replica.
-    // TODO: Move to a ptr, as this data doesn't need to be stored in with active data.
-    // pub identity: Option<Cid>,
-    pub identity: Option<Identity>,
+    /// An [`Identity`] pointer for this Replica.
+    pub identity: Option<Cid>,
 }
 /// Default state of selected repo/branch for stateless use cases, like CLI or app warmups.
 ///
@@ -158,17 +245,46 @@ pub struct Branches {
     feature = "rkyv",
     derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)
 )]
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Identity {
-    pub claimed_replicas: BTreeSet<Rid>,
+    /// Replicas claimed by this replica, each with the acknowledgement signature of the claimed
+    /// replica.
+    pub claimed_replicas: BTreeMap<Rid, ReplicaSig>,
     // pub metadata: CrdtMap<String, Value>
 }
+impl Identity {
+    /// The claimed replicas of `claimer` with a valid acknowledgement.
+    ///
+    /// Identities are read from other replicas, so acknowledgements must be verified by the reader
+    /// rather than trusted.
+    pub fn verified_replicas<'a>(&'a self, claimer: &'a Rid) -> impl Iterator<Item = Rid> + 'a {
+        self.claimed_replicas
+            .iter()
+            .filter(|(rid, ack)| rid.verify(&claim_cid(claimer, rid), ack).is_ok())
+            .map(|(rid, _)| *rid)
+    }
+}
+/// The Cid of the statement "`claimer` claims `claimed`", as signed by the claimed replica to
+/// acknowledge the claim.
+pub fn claim_cid(claimer: &Rid, claimed: &Rid) -> Cid {
+    let mut buf = b"fixity/claim/".to_vec();
+    buf.extend_from_slice(claimer.as_ref());
+    buf.extend_from_slice(claimed.as_ref());
+    <Cid as ContentId>::hash(&buf)
+}
+/// Acknowledge the claim of `claimer` over the replica of `key`, for use with
+/// [`ReplicaLog::claim_replica`].
+pub fn ack_claim(key: &ReplicaKey, claimer: &Rid) -> ReplicaSig {
+    key.sign(&claim_cid(claimer, &key.rid()))
+}
 impl<S> DefaultContainer<S> for ReplicaLog<S> {
     fn default_container(store: &Arc<S>) -> ReplicaLog<S> {
         ReplicaLog {
             clean: true,
             tip_cid: None,
             tip: Default::default(),
+            replica: None,
+            key: None,
             _store: Arc::clone(store),
         }
     }
@@ -179,17 +295,20 @@ where
     S: ContentStore,
 {
     async fn open(store: &Arc<S>, cid: &Cid) -> Result<ReplicaLog<S>, StoreError> {
-        let tip_cid = Some(*cid);
-        let tip = store.get_owned_unchecked::<LogEntry>(cid).await?;
+        let signed = store.get_owned_unchecked::<SignedLogEntry>(cid).await?;
+        signed.replica.verify(&signed.entry, &signed.replica_sig)?;
+        let tip = store.get_owned_unchecked::<LogEntry>(&signed.entry).await?;
         Ok(ReplicaLog {
             clean: true,
-            tip_cid,
+            tip_cid: Some(*cid),
             tip,
+            replica: Some(signed.replica),
+            key: None,
             _store: Arc::clone(store),
         })
     }
     async fn save(&mut self, store: &Arc<S>) -> Result<Cid, StoreError> {
-        let previous = match (self.clean, self.tip_cid.take()) {
+        let previous = match (self.clean, self.tip_cid) {
             // Data is clean, and there is a previous cid, so we can return that and not bother
             // writing an unchanged data structure.
             (true, Some(tip)) => return Ok(tip),
@@ -201,10 +320,17 @@ where
             // Data is dirty, write it.
             (false, tip) => tip,
         };
+        let key = self.key.as_ref().ok_or(StoreError::MissingReplicaKey)?;
         let entry = &mut self.tip;
         entry.previous = previous;
         // TODO: standardized error, not initialized or something?
-        let tip_cid = store.put(&*entry).await?;
+        let entry_cid = store.put(&*entry).await?;
+        let signed = SignedLogEntry {
+            entry: entry_cid,
+            replica: key.rid(),
+            replica_sig: key.sign(&entry_cid),
+        };
+        let tip_cid = store.put(&signed).await?;
         self.tip_cid = Some(tip_cid);
         self.clean = true;
         Ok(tip_cid)
@@ -214,7 +340,7 @@ where
         store: &Arc<S>,
         cids_buf: &mut Vec<Cid>,
     ) -> Result<(), StoreError> {
-        let previous = match (self.clean, self.tip_cid.take()) {
+        let previous = match (self.clean, self.tip_cid) {
             (true, Some(tip)) => {
                 cids_buf.push(tip);
                 return Ok(());
@@ -222,12 +348,20 @@ where
             (true, None) => None,
             (false, tip) => tip,
         };
+        let key = self.key.as_ref().ok_or(StoreError::MissingReplicaKey)?;
         let entry = &mut self.tip;
         entry.previous = previous;
         let entry = &mut self.tip;
         // TODO: standardized error, not initialized or something?
         store.put_with_cids(entry, cids_buf).await?;
         // TODO: add standardized error for cid missing from buf, store did not write to cid buf
+        let entry_cid = cids_buf.last().cloned().unwrap();
+        let signed = SignedLogEntry {
+            entry: entry_cid,
+            replica: key.rid(),
+            replica_sig: key.sign(&entry_cid),
+        };
+        store.put_with_cids(&signed, cids_buf).await?;
         let tip_cid = cids_buf.last().cloned().unwrap();
         self.tip_cid = Some(tip_cid);
         self.clean = true;
@@ -251,10 +385,13 @@ pub mod test {
     use super::*;
     use fixity_store::stores::memory::Memory;
 
+    fn key(seed: u8) -> ReplicaKey {
+        ReplicaKey::from_seed([seed; 32])
+    }
     #[tokio::test]
     async fn poc() {
         let store = Arc::new(Memory::default());
-        let mut rl = ReplicaLog::default_container(&store);
+        let mut rl = ReplicaLog::with_key(&store, key(1));
         rl.set_repo_tip("foo", 1.into());
         dbg!(&rl);
         let cid = rl.save(&store).await.unwrap();
@@ -266,7 +403,7 @@ pub mod test {
     #[tokio::test]
     async fn set_repo_tip() {
         let store = Arc::new(Memory::default());
-        let mut rl = ReplicaLog::default_container(&store);
+        let mut rl = ReplicaLog::with_key(&store, key(1));
         assert!(rl.clean);
         assert_eq!(rl.repo_tip("foo"), None);
         rl.set_repo_tip("foo", 1.into());
@@ -281,4 +418,87 @@ pub mod test {
         assert_eq!(rl.repo_tip("foo"), Some(Cid::from(2)));
         assert!(!rl.clean);
     }
+    #[tokio::test]
+    async fn signed() {
+        let store = Arc::new(Memory::default());
+        let mut rl = ReplicaLog::default_container(&store);
+        rl.set_repo_tip("foo", 1.into());
+        assert!(matches!(
+            rl.save(&store).await,
+            Err(StoreError::MissingReplicaKey)
+        ));
+        rl.set_key(key(1)).unwrap();
+        let cid = rl.save(&store).await.unwrap();
+        let mut rl = ReplicaLog::open_replica(&store, &cid, key(1).rid())
+            .await
+            .unwrap();
+        assert_eq!(rl.repo_tip("foo"), Some(Cid::from(1)));
+        assert!(matches!(
+            ReplicaLog::open_replica(&store, &cid, key(2).rid()).await,
+            Err(StoreError::UnexpectedReplica { .. })
+        ));
+        assert!(matches!(
+            rl.set_key(key(2)),
+            Err(StoreError::UnexpectedReplica { .. })
+        ));
+        rl.set_key(key(1)).unwrap();
+        rl.set_repo_tip("foo", 2.into());
+        let next_cid = rl.save(&store).await.unwrap();
+        let signed = store
+            .get_owned_unchecked::<SignedLogEntry>(&next_cid)
+            .await
+            .unwrap();
+        let entry = store
+            .get_owned_unchecked::<LogEntry>(&signed.entry)
+            .await
+            .unwrap();
+        assert_eq!(entry.previous, Some(cid));
+    }
+    #[tokio::test]
+    async fn forged() {
+        let store = Arc::new(Memory::default());
+        let mut rl = ReplicaLog::with_key(&store, key(1));
+        rl.set_repo_tip("foo", 1.into());
+        let cid = rl.save(&store).await.unwrap();
+        let signed = store
+            .get_owned_unchecked::<SignedLogEntry>(&cid)
+            .await
+            .unwrap();
+        // Replica 2 claiming to be replica 1.
+        let forged = SignedLogEntry {
+            entry: signed.entry,
+            replica: key(1).rid(),
+            replica_sig: key(2).sign(&signed.entry),
+        };
+        let forged_cid = store.put(&forged).await.unwrap();
+        assert!(matches!(
+            ReplicaLog::open(&store, &forged_cid).await,
+            Err(StoreError::Signature(_))
+        ));
+    }
+    #[tokio::test]
+    async fn claim_replica() {
+        let store = Arc::new(Memory::default());
+        let mut rl = ReplicaLog::with_key(&store, key(1));
+        let claimer = key(1).rid();
+        // An ack for a different claimer is invalid.
+        let bad_ack = ack_claim(&key(2), &key(3).rid());
+        assert!(matches!(
+            rl.claim_replica(&store, key(2).rid(), bad_ack).await,
+            Err(StoreError::Signature(_))
+        ));
+        assert!(rl.clean);
+        let ack = ack_claim(&key(2), &claimer);
+        rl.claim_replica(&store, key(2).rid(), ack).await.unwrap();
+        assert!(!rl.clean);
+        let cid = rl.save(&store).await.unwrap();
+        let rl = ReplicaLog::open(&store, &cid).await.unwrap();
+        let identity = rl.identity(&store).await.unwrap();
+        assert_eq!(
+            identity.verified_replicas(&claimer).collect::<Vec<_>>(),
+            vec![key(2).rid()]
+        );
+        // Claims copied into another replica's identity do not verify.
+        assert_eq!(identity.verified_replicas(&key(3).rid()).count(), 0);
+    }
 }
@@ -0,0 +1,400 @@
+use crate::chunk::{self, StoredChunks};
+use async_trait::async_trait;
+use fixity_store::{
+    container::{ContainerDescription, DescribeContainer, PersistContainer, ReconcileContainer},
+    content_store::ContentStore,
+    contentid::Cid,
+    deser::{Deserialize, Serialize},
+    deser_ext::DeserExt,
+    replicaid::Rid,
+    store::StoreError,
+};
+use std::{collections::HashMap, fmt, sync::Arc};
+
+/// A unique identifier of an inserted element, the Lamport timestamp of the insert and the
+/// replica that inserted it.
+pub type SeqId = (u64, Rid);
+/// A chunk of the persisted form of a [`Sequence`], runs of elements in document order.
+pub type Chunk<T> = Vec<Run<T>>;
+/// The root of the persisted form of a [`Sequence`], the chunk pointers in document order.
+type Root = Vec<Cid>;
+
+/// A sequence of values that can be inserted and removed by index on any replica and merged,
+/// such as the characters of a collaboratively edited document.
+///
+/// Implemented as a Replicated Growable Array (RGA), where each element is inserted after an
+/// origin element and concurrent inserts after the same origin are ordered by their [`SeqId`].
+/// Removed elements are kept as tombstones, as concurrent inserts may reference them.
+//
+// PERF: Indexes are resolved with a linear scan, a tree of visible counts would make inserts and
+// merges logarithmic for long sequences.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sequence<T> {
+    /// The greatest Lamport timestamp this sequence has observed.
+    clock: u64,
+    elements: Vec<Element<T>>,
+    stored: StoredChunks,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Element<T> {
+    id: SeqId,
+    /// The element this element was inserted after, or `None` for the start of the sequence.
+    origin: Option<SeqId>,
+    value: T,
+    deleted: bool,
+}
+/// Consecutive elements inserted by a single replica each directly after the previous, as
+/// typing produces, persisted without repeating the ids and origins of each element.
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)
+)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run<T> {
+    start: SeqId,
+    origin: Option<SeqId>,
+    deleted: bool,
+    values: Vec<T>,
+}
+impl<T> Sequence<T> {
+    pub fn new() -> Self {
+        Self {
+            clock: 0,
+            elements: Vec::new(),
+            stored: StoredChunks::default(),
+        }
+    }
+    /// The number of visible, aka not removed, values.
+    pub fn len(&self) -> usize {
+        self.elements.iter().filter(|e| !e.deleted).count()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.elements
+            .iter()
+            .filter(|e| !e.deleted)
+            .map(|e| &e.value)
+    }
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.iter().nth(index)
+    }
+    /// Insert the value at `index` as the given replica.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(&mut self, rid: Rid, index: usize, value: T) {
+        let origin = match index.checked_sub(1) {
+            Some(prev) => Some(self.elements[self.position_of_index(prev)].id),
+            None => None,
+        };
+        let element = Element {
+            id: (self.clock + 1, rid),
+            origin,
+            value,
+            deleted: false,
+        };
+        self.integrate(element)
+            .expect("origin of a local insert is present");
+    }
+    /// Remove the value at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len`.
+    pub fn remove(&mut self, index: usize) {
+        let position = self.position_of_index(index);
+        self.elements[position].deleted = true;
+    }
+    /// Merge another sequence into this one.
+    ///
+    /// The other sequence must be a full sequence or a diff against a sequence this one has
+    /// merged, such that the origin of every insert is known.
+    pub fn join(&mut self, other: Self) -> Result<(), StoreError> {
+        for element in other.elements {
+            match self.position(&element.id) {
+                Some(position) => self.elements[position].deleted |= element.deleted,
+                None => self.integrate(element)?,
+            }
+        }
+        Ok(())
+    }
+    fn position(&self, id: &SeqId) -> Option<usize> {
+        self.elements.iter().position(|e| &e.id == id)
+    }
+    /// The position in `elements` of the visible value at `index`.
+    fn position_of_index(&self, index: usize) -> usize {
+        self.elements
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| !e.deleted)
+            .nth(index)
+            .map(|(position, _)| position)
+            .unwrap_or_else(|| panic!("index {} out of bounds of {}", index, self.len()))
+    }
+    fn integrate(&mut self, element: Element<T>) -> Result<(), StoreError> {
+        let mut position = match element.origin {
+            Some(origin) => {
+                self.position(&origin)
+                    // NIT: An unknown origin is a diff merged into the wrong sequence, a
+                    // dedicated error would be clearer.
+                    .ok_or(StoreError::UnmergableType)?
+                    + 1
+            },
+            None => 0,
+        };
+        // Concurrent inserts after the same origin, and everything inserted after them, have
+        // greater ids. Skipping them orders siblings by descending id.
+        while position < self.elements.len() && self.elements[position].id > element.id {
+            position += 1;
+        }
+        self.clock = self.clock.max(element.id.0);
+        self.elements.insert(position, element);
+        Ok(())
+    }
+}
+impl Sequence<char> {
+    /// Insert each char of `s` starting at `index`, as the given replica.
+    pub fn insert_str(&mut self, rid: Rid, index: usize, s: &str) {
+        for (i, c) in s.chars().enumerate() {
+            self.insert(rid, index + i, c);
+        }
+    }
+}
+impl fmt::Display for Sequence<char> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.iter().try_for_each(|c| write!(f, "{}", c))
+    }
+}
+impl<T> Default for Sequence<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T> DescribeContainer for Sequence<T> {
+    fn description() -> ContainerDescription {
+        // TODO: Describe `T`, once params are supported.
+        ContainerDescription {
+            name: "Sequence",
+            params: Default::default(),
+        }
+    }
+}
+impl<T: Clone> Sequence<T> {
+    /// Split the elements into chunks of runs, with boundaries defined by the ids of the elements
+    /// such that an edit only changes the chunk it falls in.
+    fn to_chunks(&self) -> Vec<Chunk<T>> {
+        let mut chunks = Vec::new();
+        let mut chunk: Chunk<T> = Vec::new();
+        let mut prev: Option<&Element<T>> = None;
+        for element in self.elements.iter() {
+            let continues_run = matches!(prev, Some(prev)
+                if element.id == (prev.id.0 + 1, prev.id.1)
+                    && element.origin == Some(prev.id)
+                    && element.deleted == prev.deleted);
+            match chunk.last_mut() {
+                Some(run) if continues_run => run.values.push(element.value.clone()),
+                _ => chunk.push(Run {
+                    start: element.id,
+                    origin: element.origin,
+                    deleted: element.deleted,
+                    values: vec![element.value.clone()],
+                }),
+            }
+            prev = Some(element);
+            if is_boundary(&element.id) {
+                chunks.push(std::mem::take(&mut chunk));
+                prev = None;
+            }
+        }
+        if !chunk.is_empty() {
+            chunks.push(chunk);
+        }
+        chunks
+    }
+    fn from_chunks(chunks: impl IntoIterator<Item = Chunk<T>>) -> Self {
+        let mut seq = Self::new();
+        for run in chunks.into_iter().flatten() {
+            let (lamport, rid) = run.start;
+            let mut origin = run.origin;
+            for (i, value) in run.values.into_iter().enumerate() {
+                let id = (lamport + i as u64, rid);
+                seq.clock = seq.clock.max(id.0);
+                seq.elements.push(Element {
+                    id,
+                    origin,
+                    value,
+                    deleted: run.deleted,
+                });
+                origin = Some(id);
+            }
+        }
+        seq
+    }
+}
+fn is_boundary((lamport, rid): &SeqId) -> bool {
+    let mut buf = lamport.to_be_bytes().to_vec();
+    buf.extend_from_slice(rid.as_ref());
+    chunk::is_boundary(&buf)
+}
+#[async_trait]
+impl<T, S> PersistContainer<S> for Sequence<T>
+where
+    S: ContentStore,
+    T: Clone + Send + Sync,
+    Chunk<T>: Serialize + Deserialize,
+{
+    async fn open(store: &Arc<S>, cid: &Cid) -> Result<Self, StoreError> {
+        let chunk_cids = store.get_owned_unchecked::<Root>(cid).await?;
+        let mut chunks = Vec::with_capacity(chunk_cids.len());
+        for chunk_cid in chunk_cids.iter() {
+            chunks.push(store.get_owned_unchecked::<Chunk<T>>(chunk_cid).await?);
+        }
+        Ok(Self {
+            stored: StoredChunks::new(&chunk_cids),
+            ..Self::from_chunks(chunks)
+        })
+    }
+    async fn save(&mut self, store: &Arc<S>) -> Result<Cid, StoreError> {
+        let mut cids = Vec::new();
+        self.save_with_cids(store, &mut cids).await?;
+        Ok(cids.pop().expect("root cid written"))
+    }
+    async fn save_with_cids(
+        &mut self,
+        store: &Arc<S>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<(), StoreError> {
+        let chunks = self.to_chunks();
+        let chunk_cids: Root = self.stored.save(store, chunks, cids_buf).await?;
+        store.put_with_cids(&chunk_cids, cids_buf).await
+    }
+}
+#[async_trait]
+impl<T, S> ReconcileContainer<S> for Sequence<T>
+where
+    S: ContentStore,
+    T: Clone + Send + Sync,
+    Chunk<T>: Serialize + Deserialize,
+{
+    async fn merge(&mut self, store: &Arc<S>, other: &Cid) -> Result<(), StoreError> {
+        let other = Self::open(store, other).await?;
+        self.join(other)
+    }
+    async fn diff(&mut self, store: &Arc<S>, other: &Cid) -> Result<Self, StoreError> {
+        let other = Self::open(store, other).await?;
+        let other_deleted = other
+            .elements
+            .iter()
+            .map(|e| (e.id, e.deleted))
+            .collect::<HashMap<_, _>>();
+        // Elements are kept in document order, such that the origin of every insert in the diff
+        // precedes it, either in the diff or in `other`.
+        let elements = self
+            .elements
+            .iter()
+            .filter(|e| match other_deleted.get(&e.id) {
+                Some(other_deleted) => e.deleted && !other_deleted,
+                None => true,
+            })
+            .cloned()
+            .collect();
+        Ok(Self {
+            clock: self.clock,
+            elements,
+            stored: StoredChunks::default(),
+        })
+    }
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use fixity_store::stores::memory::Memory;
+
+    #[test]
+    fn insert_remove() {
+        let mut a = Sequence::new();
+        a.insert_str(1.into(), 0, "helo");
+        a.insert(1.into(), 3, 'l');
+        a.insert_str(1.into(), 5, " world");
+        assert_eq!(a.to_string(), "hello world");
+        a.remove(0);
+        a.insert(1.into(), 0, 'j');
+        assert_eq!(a.to_string(), "jello world");
+        assert_eq!(a.len(), 11);
+        assert_eq!(a.get(1), Some(&'e'));
+    }
+    #[test]
+    fn concurrent() {
+        let mut base = Sequence::new();
+        base.insert_str(1.into(), 0, "ab");
+        let mut a = base.clone();
+        let mut b = base.clone();
+        // Concurrent inserts at the same index keep each replica's run together.
+        a.insert_str(1.into(), 1, "foo");
+        b.insert_str(2.into(), 1, "bar");
+        b.remove(0);
+        let mut ab = a.clone();
+        ab.join(b.clone()).unwrap();
+        let mut ba = b.clone();
+        ba.join(a.clone()).unwrap();
+        assert_eq!(ab, ba);
+        assert_eq!(ab.to_string(), "barfoob");
+    }
+    #[tokio::test]
+    async fn merge_diff() {
+        let store = Memory::test();
+        let mut a = Sequence::new();
+        a.insert_str(1.into(), 0, "hello");
+        let a_cid = a.save(&store).await.unwrap();
+        let mut b = Sequence::<char>::open(&store, &a_cid).await.unwrap();
+        assert_eq!(a, b);
+        b.insert_str(2.into(), 5, " world");
+        b.remove(0);
+        a.insert(1.into(), 0, '>');
+        let mut diff = b.diff(&store, &a_cid).await.unwrap();
+        assert_eq!(diff.elements.len(), 7, "the remove and the inserts");
+        let diff_cid = diff.save(&store).await.unwrap();
+        let b_cid = b.save(&store).await.unwrap();
+        let mut via_diff = a.clone();
+        via_diff.merge(&store, &diff_cid).await.unwrap();
+        a.merge(&store, &b_cid).await.unwrap();
+        assert_eq!(a.to_string(), ">ello world");
+        assert_eq!(via_diff, a);
+    }
+    #[tokio::test]
+    async fn chunked() {
+        let store = Memory::test();
+        let mut a = Sequence::new();
+        let text = "lorem ipsum dolor sit amet ".repeat(100);
+        a.insert_str(1.into(), 0, &text);
+        let chunks = a.to_chunks();
+        assert!(chunks.len() > 1, "long sequences are split into chunks");
+        assert!(
+            chunks.iter().all(|chunk| chunk.len() == 1),
+            "typed text is persisted as runs"
+        );
+        let a_cid = a.save(&store).await.unwrap();
+        let b = Sequence::<char>::open(&store, &a_cid).await.unwrap();
+        assert_eq!(a, b);
+        // Boundaries are defined by ids, so an edit only affects its own chunk.
+        a.remove(1_000);
+        a.insert(2.into(), 1_500, '!');
+        let changed = a
+            .to_chunks()
+            .iter()
+            .filter(|chunk| !chunks.contains(chunk))
+            .count();
+        assert_eq!(changed, 2);
+        // Which are the only chunks written, along with the root.
+        let mut cids = Vec::new();
+        a.save_with_cids(&store, &mut cids).await.unwrap();
+        assert_eq!(cids.len(), 3);
+        let b = Sequence::<char>::open(&store, cids.last().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(a, b);
+    }
+}
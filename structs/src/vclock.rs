@@ -0,0 +1,181 @@
+use crate::gcounter::{GCounter, GCounterInt, IVec};
+use async_trait::async_trait;
+use fixity_store::{
+    container::{ContainerDescription, DescribeContainer, PersistContainer, ReconcileContainer},
+    content_store::ContentStore,
+    contentid::Cid,
+    deser_ext::DeserExt,
+    replicaid::Rid,
+    store::StoreError,
+};
+use std::{cmp::Ordering, sync::Arc};
+
+/// A vector clock over replicas, for tracking causality between replicas.
+///
+/// Clocks are partially ordered, where `None` from [`PartialOrd::partial_cmp`] means the clocks
+/// are concurrent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VClock(GCounter);
+impl VClock {
+    pub fn new() -> Self {
+        Self(GCounter::new())
+    }
+    /// Advance the clock of the given replica.
+    pub fn inc(&mut self, rid: Rid) {
+        self.0.inc(rid)
+    }
+    /// The clock of the given replica, `0` if the replica has never been seen.
+    pub fn get(&self, rid: &Rid) -> GCounterInt {
+        self.0.get(rid).unwrap_or(0)
+    }
+    pub(crate) fn as_ivec(&self) -> &IVec {
+        self.0.as_ivec()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&Rid, GCounterInt)> {
+        self.0.as_ivec().iter().map(|(rid, i)| (rid, *i))
+    }
+    /// Whether this clock causally precedes `other`.
+    pub fn happens_before(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Less)
+    }
+    /// Whether neither this clock nor `other` causally precedes the other.
+    pub fn concurrent(&self, other: &Self) -> bool {
+        self.partial_cmp(other).is_none()
+    }
+    /// Merge `other` into this clock, producing the least clock that both clocks happen before or
+    /// equal.
+    pub fn join(&mut self, other: &Self) {
+        self.0.merge_ivec(other.0.as_ivec().clone())
+    }
+}
+impl From<GCounter> for VClock {
+    fn from(counter: GCounter) -> Self {
+        Self(counter)
+    }
+}
+impl Default for VClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl PartialOrd for VClock {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        // NIT: This func is rather expensive :/
+        use Ordering::{Equal, Greater, Less};
+        // Avoiding full map alloc by iter instead of merging
+        let iter = self.iter().map(|(rid, lhs)| (lhs, other.get(rid))).chain(
+            // if we exhaust items in Self and haven't returned None (Concurrent)
+            // yet, we chain the items only in Other.
+            other
+                .iter()
+                .filter(|(rid, _)| self.0.get(rid).is_none())
+                .map(|(_, rhs)| (0, rhs)),
+        );
+        // PERF: This could be implemented as a TryFold with .ok() as well.
+        // The early return as an escape hatch might be less work than Err()
+        // for the compiler though, hard to say, worth checking in the future.
+        let mut ord = Equal;
+        for (lhs, rhs) in iter {
+            ord = match (ord, lhs.cmp(&rhs)) {
+                (Equal, Equal) => Equal,
+                (Equal, Greater) | (Greater, Equal) | (Greater, Greater) => Greater,
+                (Equal, Less) | (Less, Equal) | (Less, Less) => Less,
+                (Greater, Less) | (Less, Greater) => return None,
+            }
+        }
+        Some(ord)
+    }
+}
+impl DescribeContainer for VClock {
+    fn description() -> ContainerDescription {
+        ContainerDescription {
+            name: "VClock",
+            params: Default::default(),
+        }
+    }
+}
+#[async_trait]
+impl<S> PersistContainer<S> for VClock
+where
+    S: ContentStore,
+{
+    async fn open(store: &Arc<S>, cid: &Cid) -> Result<Self, StoreError> {
+        let inner = store.get_owned_unchecked::<IVec>(cid).await?;
+        Ok(Self(GCounter::from_ivec(inner)))
+    }
+    async fn save(&mut self, store: &Arc<S>) -> Result<Cid, StoreError> {
+        store.put::<IVec>(self.0.as_ivec()).await
+    }
+    async fn save_with_cids(
+        &mut self,
+        store: &Arc<S>,
+        cids_buf: &mut Vec<Cid>,
+    ) -> Result<(), StoreError> {
+        store
+            .put_with_cids::<IVec>(self.0.as_ivec(), cids_buf)
+            .await
+    }
+}
+#[async_trait]
+impl<S> ReconcileContainer<S> for VClock
+where
+    S: ContentStore,
+{
+    async fn merge(&mut self, store: &Arc<S>, other: &Cid) -> Result<(), StoreError> {
+        let other = store.get_owned_unchecked::<IVec>(other).await?;
+        self.0.merge_ivec(other);
+        Ok(())
+    }
+    async fn diff(&mut self, store: &Arc<S>, other: &Cid) -> Result<Self, StoreError> {
+        let other = store.get_owned_unchecked::<IVec>(other).await?;
+        Ok(Self(GCounter::from_ivec(self.0.diff_ivec(&other))))
+    }
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use fixity_store::{container::DefaultContainer, stores::memory::Memory};
+
+    #[test]
+    fn causality() {
+        let mut a = VClock::new();
+        let mut b = VClock::new();
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Equal));
+        a.inc(1.into());
+        assert!(b.happens_before(&a));
+        assert!(!a.happens_before(&b));
+        b.inc(2.into());
+        assert!(
+            a.concurrent(&b),
+            "each has a replica the other has not seen"
+        );
+        assert!(b.concurrent(&a));
+        b.join(&a);
+        assert!(a.happens_before(&b));
+        a.join(&b);
+        assert_eq!(a, b);
+        a.inc(1.into());
+        a.inc(2.into());
+        assert!(b.happens_before(&a));
+    }
+    #[tokio::test]
+    async fn persist_merge() {
+        let store = Memory::test();
+        let mut a = VClock::default_container(&store);
+        a.inc(1.into());
+        let mut b = VClock::default_container(&store);
+        b.inc(2.into());
+        b.inc(2.into());
+        let b_cid = b.save(&store).await.unwrap();
+        assert_eq!(VClock::open(&store, &b_cid).await.unwrap(), b);
+        a.merge(&store, &b_cid).await.unwrap();
+        assert_eq!(a.get(&1.into()), 1);
+        assert_eq!(a.get(&2.into()), 2);
+        assert!(b.happens_before(&a));
+        let a_cid = a.save(&store).await.unwrap();
+        let diff = b.diff(&store, &a_cid).await.unwrap();
+        assert_eq!(diff, VClock::new(), "a has seen everything b has");
+        let diff = a.diff(&store, &b_cid).await.unwrap();
+        assert_eq!(diff.iter().collect::<Vec<_>>(), vec![(&1.into(), 1)]);
+    }
+}